@@ -2,9 +2,11 @@ import importlib.metadata
 
 __version__ = importlib.metadata.version("rfsspec")
 
+from rfsspec.rfsspec import (ChecksumError, FileChangedError, ObjectArchivedError,
+                             PreconditionFailedError)
 from rfsspec.http import RustyHTTPFileSystem
 from rfsspec.s3 import RustyS3FileSystem
 from rfsspec.gcs import RustyGCSFileSystem
 from rfsspec.azure import RustyAzureFileSystem
 
-__all__ = ["RustyS3FileSystem", "RustyHTTPFileSystem", "RustyGCSFileSystem", "RustyAzureFileSystem"]
+__all__ = ["ChecksumError", "FileChangedError", "ObjectArchivedError", "PreconditionFailedError", "RustyS3FileSystem", "RustyHTTPFileSystem", "RustyGCSFileSystem", "RustyAzureFileSystem"]
@@ -1,8 +1,10 @@
 from __future__ import absolute_import, division, print_function
 
-from rfsspec.rfsspec import azure_cat_ranges
+from rfsspec.rfsspec import (azure_cat_ranges, azure_glob, azure_list_pages, azure_pipe,
+                             azure_sign, azure_list_containers, azure_create_container,
+                             azure_delete_container, azure_delete, azure_info_many, azure_du)
 
-from fsspec.spec import AbstractFileSystem
+from fsspec.spec import AbstractFileSystem, AbstractBufferedFile
 
 
 class RustyAzureFileSystem(AbstractFileSystem):
@@ -15,17 +17,24 @@ class RustyAzureFileSystem(AbstractFileSystem):
             account,
             anon=False,
             key=None,
+            dir_markers=False,
             **storage_options,
     ):
         """
+        dir_markers: bool
+            Have ``mkdir`` write an empty "path/" blob, so that a directory
+            exists before it holds any files, and ``rmdir`` remove it
         """
         if key is None and anon is False:
             raise ValueError("If not anonymous, must supply a key")
         self.kwargs = dict(anon=anon, account=account, key=key)
+        self.dir_markers = dir_markers
         super().__init__(self, **storage_options)
 
-    def cat_file(self, url, start=None, end=None, **kwargs):
-        return azure_cat_ranges([url], start=[start or 0], end=[end or 0], **self.kwargs)[0]
+    def cat_file(self, url, start=None, end=None, if_match=None, **kwargs):
+        """Bytes of the blob; with if_match, raise FileChangedError if its ETag differs"""
+        return azure_cat_ranges([url], start=[start or 0], end=[end or 0],
+                                if_match=[if_match], **self.kwargs)[0]
 
     def cat(self, path, recursive=False, on_error="raise", start=0, end=0, **kwargs):
         paths = [path] if isinstance(path, str) else path
@@ -42,3 +51,151 @@ class RustyAzureFileSystem(AbstractFileSystem):
 
     def cat_ranges(self, urls, starts, ends, **kwargs):
         return azure_cat_ranges(urls, start=starts, end=ends, **self.kwargs)
+
+    def _open(self, path, mode="rb", **kwargs):
+        """File for reading; every block must match the ETag seen at open"""
+        if "r" not in mode:
+            raise NotImplementedError("Only reading is supported; write with pipe_file")
+        path = self._strip_protocol(path)
+        info = self.info_many([path])[0]
+        if info is None:
+            raise FileNotFoundError(path)
+        return RustyAzureFile(self, path, mode=mode, size=info["size"],
+                              etag=info.get("ETag") or None, **kwargs)
+
+    def du(self, path, total=True, maxdepth=None, **kwargs):
+        """Total size of the files below path or, if not total, a dict of the
+        total in each directory below it, path included
+
+        maxdepth: count only files up to this many levels below path
+        """
+        return azure_du(self._strip_protocol(path), total=total, maxdepth=maxdepth,
+                        **self.kwargs)
+
+    def glob(self, path, maxdepth=None, detail=False, **kwargs):
+        out = azure_glob(self._strip_protocol(path), maxdepth=maxdepth, **self.kwargs)
+        for o in out:
+            o["size"] = int(o["size"])
+        if detail:
+            return {o["name"]: o for o in out}
+        return [o["name"] for o in out]
+
+    def info_many(self, paths, concurrency=32):
+        """Info of each blob, or None where there is no such blob, in order
+
+        Up to ``concurrency`` requests run at once; directories give None.
+        """
+        paths = [self._strip_protocol(p) for p in paths]
+        out = azure_info_many(paths, concurrency=concurrency, **self.kwargs)
+        for path, info in zip(paths, out):
+            if info is not None:
+                info["name"] = path
+                info["size"] = int(info["size"])
+        return out
+
+    def exists_many(self, paths, concurrency=32):
+        """Whether each path is a blob, in order"""
+        return [info is not None for info in self.info_many(paths, concurrency=concurrency)]
+
+    def ls(self, path, detail=True, **kwargs):
+        path = self._strip_protocol(path)
+        if path:
+            out = list(self.ls_iter(path))
+        else:
+            out = azure_list_containers(**self.kwargs)
+            for o in out:
+                o["size"] = int(o["size"])
+        if detail:
+            return out
+        return [o["name"] for o in out]
+
+    def ls_iter(self, path, recursive=False):
+        """Yield entries of the listing of path, recursive or not, as they arrive"""
+        for page in azure_list_pages(self._strip_protocol(path), recursive=recursive,
+                                        **self.kwargs):
+            for o in page:
+                o["size"] = int(o["size"])
+                yield o
+
+    def pipe_file(self, path, value, if_none_match=None, if_match=None, **kwargs):
+        """Write bytes to path
+
+        if_none_match="*" writes only a new blob, and if_match only replaces a
+        blob with that ETag; otherwise PreconditionFailedError is raised.
+        """
+        return azure_pipe(self._strip_protocol(path), value, if_none_match=if_none_match,
+                          if_match=if_match, **self.kwargs)
+
+    @property
+    def _key(self):
+        if self.kwargs["key"] is None:
+            raise ValueError("Creating and deleting containers needs the account key")
+        return self.kwargs["key"]
+
+    def mkdir(self, path, create_parents=True, **kwargs):
+        """Create a container, or a directory in one
+
+        FileExistsError if the container already exists. Below the container,
+        directories only exist while they hold files, unless the filesystem
+        has ``dir_markers``.
+        """
+        path = self._strip_protocol(path).rstrip("/")
+        container, _, name = path.partition("/")
+        if not name or (create_parents and not self._container_exists(container)):
+            try:
+                azure_create_container(container, self.kwargs["account"], self._key)
+            except FileExistsError:
+                if not name:
+                    raise
+        if name and self.dir_markers:
+            azure_pipe(path + "/", b"", **self.kwargs)
+
+    def _container_exists(self, container):
+        try:
+            next(iter(azure_list_pages(container, **self.kwargs)), None)
+        except FileNotFoundError:
+            return False
+        except PermissionError:
+            # a container we may not list
+            return True
+        return True
+
+    def makedirs(self, path, exist_ok=False):
+        try:
+            self.mkdir(path, create_parents=True)
+        except FileExistsError:
+            if not exist_ok:
+                raise
+
+    def rmdir(self, path):
+        """Delete an empty container, or with ``dir_markers``, a directory's marker
+
+        FileExistsError if the container still holds blobs.
+        """
+        path = self._strip_protocol(path).rstrip("/")
+        container, _, name = path.partition("/")
+        if not name:
+            azure_delete_container(container, self.kwargs["account"], self._key)
+        elif self.dir_markers:
+            azure_delete(path + "/", **self.kwargs)
+
+    def rm_file(self, path):
+        azure_delete(self._strip_protocol(path), **self.kwargs)
+
+    def sign(self, path, expiration=100, method="GET", **kwargs):
+        """SAS URL for path, valid for ``expiration`` seconds"""
+        if self.kwargs["key"] is None:
+            raise ValueError("Signing needs the account key")
+        return azure_sign(self._strip_protocol(path), self.kwargs["account"], self.kwargs["key"],
+                          expiration=int(expiration), method=method)
+
+
+class RustyAzureFile(AbstractBufferedFile):
+
+    def __init__(self, fs, path, etag=None, **kwargs):
+        # the ETag at open time, which every block must match
+        self.etag = etag
+        super().__init__(fs, path, **kwargs)
+
+    def _fetch_range(self, start, end):
+        return self.fs.cat_file(self.path, start=start, end=end, if_match=self.etag)
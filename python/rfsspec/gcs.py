@@ -1,8 +1,10 @@
 from __future__ import absolute_import, division, print_function
 
-from rfsspec.rfsspec import gcs_cat_ranges
+from rfsspec.rfsspec import (gcs_cat_ranges, gcs_glob, gcs_list_pages, gcs_pipe, gcs_sign,
+                             gcs_list_buckets, gcs_create_bucket, gcs_delete_bucket, gcs_delete,
+                             gcs_info_many, gcs_du)
 
-from fsspec.spec import AbstractFileSystem
+from fsspec.spec import AbstractFileSystem, AbstractBufferedFile
 
 
 class RustyGCSFileSystem(AbstractFileSystem):
@@ -15,17 +17,24 @@ class RustyGCSFileSystem(AbstractFileSystem):
             anon=False,
             project=None,
             requester_pays=False,
+            dir_markers=False,
             **storage_options,
     ):
         """
+        dir_markers: bool
+            Have ``mkdir`` write an empty "path/" object, so that a directory
+            exists before it holds any files, and ``rmdir`` remove it
         """
         if requester_pays and project is None:
             raise ValueError("If using requester-pays, please specify `project=`")
         self.kwargs = dict(anon=anon, project=project, requester_pays=requester_pays)
+        self.dir_markers = dir_markers
         super().__init__(self, **storage_options)
 
-    def cat_file(self, url, start=None, end=None, **kwargs):
-        return gcs_cat_ranges([url], start=[start or 0], end=[end or 0], **self.kwargs)[0]
+    def cat_file(self, url, start=None, end=None, generation=None, **kwargs):
+        """Bytes of the object; with generation, raise FileChangedError if it was replaced"""
+        return gcs_cat_ranges([url], start=[start or 0], end=[end or 0],
+                              generation=[generation], **self.kwargs)[0]
 
     def cat(self, path, recursive=False, on_error="raise", start=0, end=0, **kwargs):
         paths = [path] if isinstance(path, str) else path
@@ -42,3 +51,154 @@ class RustyGCSFileSystem(AbstractFileSystem):
 
     def cat_ranges(self, urls, starts, ends, **kwargs):
         return gcs_cat_ranges(urls, start=starts, end=ends, **self.kwargs)
+
+    def _open(self, path, mode="rb", **kwargs):
+        """File for reading; every block is read from the generation current at open"""
+        if "r" not in mode:
+            raise NotImplementedError("Only reading is supported; write with pipe_file")
+        path = self._strip_protocol(path)
+        info = self.info_many([path])[0]
+        if info is None:
+            raise FileNotFoundError(path)
+        generation = int(info["generation"]) if info.get("generation") else None
+        return RustyGCSFile(self, path, mode=mode, size=info["size"], generation=generation,
+                            **kwargs)
+
+    def du(self, path, total=True, maxdepth=None, **kwargs):
+        """Total size of the files below path or, if not total, a dict of the
+        total in each directory below it, path included
+
+        maxdepth: count only files up to this many levels below path
+        """
+        return gcs_du(self._strip_protocol(path), total=total, maxdepth=maxdepth, **self.kwargs)
+
+    def glob(self, path, maxdepth=None, detail=False, **kwargs):
+        out = gcs_glob(self._strip_protocol(path), maxdepth=maxdepth, **self.kwargs)
+        for o in out:
+            o["size"] = int(o["size"])
+        if detail:
+            return {o["name"]: o for o in out}
+        return [o["name"] for o in out]
+
+    def info_many(self, paths, concurrency=32):
+        """Info of each object, or None where there is no such object, in order
+
+        Up to ``concurrency`` requests run at once; directories give None.
+        """
+        paths = [self._strip_protocol(p) for p in paths]
+        out = gcs_info_many(paths, concurrency=concurrency, **self.kwargs)
+        for path, info in zip(paths, out):
+            if info is not None:
+                info["name"] = path
+                info["size"] = int(info["size"])
+        return out
+
+    def exists_many(self, paths, concurrency=32):
+        """Whether each path is an object, in order"""
+        return [info is not None for info in self.info_many(paths, concurrency=concurrency)]
+
+    def ls(self, path, detail=True, **kwargs):
+        path = self._strip_protocol(path)
+        if path:
+            out = list(self.ls_iter(path))
+        else:
+            out = gcs_list_buckets(self._project, anon=self.kwargs["anon"])
+            for o in out:
+                o["size"] = int(o["size"])
+        if detail:
+            return out
+        return [o["name"] for o in out]
+
+    def ls_iter(self, path, recursive=False):
+        """Yield entries of the listing of path, recursive or not, as they arrive"""
+        for page in gcs_list_pages(self._strip_protocol(path), recursive=recursive,
+                                      **self.kwargs):
+            for o in page:
+                o["size"] = int(o["size"])
+                yield o
+
+    def pipe_file(self, path, value, if_none_match=None, generation=None, **kwargs):
+        """Write bytes to path
+
+        if_none_match="*" writes only a new object, and generation only replaces
+        that generation; otherwise PreconditionFailedError is raised.
+        """
+        if if_none_match == "*":
+            generation = 0
+        return gcs_pipe(self._strip_protocol(path), value, if_generation_match=generation,
+                        **self.kwargs)
+
+    @property
+    def _project(self):
+        if self.kwargs["project"] is None:
+            raise ValueError("Listing and creating buckets needs `project=`")
+        return self.kwargs["project"]
+
+    def mkdir(self, path, create_parents=True, location=None, **kwargs):
+        """Create a bucket, or a directory in one
+
+        A new bucket goes in ``location`` (e.g., "EU"), else "US";
+        FileExistsError if it already exists. Below the bucket, directories
+        only exist while they hold files, unless the filesystem has
+        ``dir_markers``.
+        """
+        path = self._strip_protocol(path).rstrip("/")
+        bucket, _, key = path.partition("/")
+        if not key or (create_parents and not self._bucket_exists(bucket)):
+            try:
+                gcs_create_bucket(bucket, self._project, location=location,
+                                  anon=self.kwargs["anon"])
+            except FileExistsError:
+                if not key:
+                    raise
+        if key and self.dir_markers:
+            gcs_pipe(path + "/", b"", **self.kwargs)
+
+    def _bucket_exists(self, bucket):
+        try:
+            next(iter(gcs_list_pages(bucket, **self.kwargs)), None)
+        except FileNotFoundError:
+            return False
+        except PermissionError:
+            # someone else's bucket, which we may not list
+            return True
+        return True
+
+    def makedirs(self, path, exist_ok=False):
+        try:
+            self.mkdir(path, create_parents=True)
+        except FileExistsError:
+            if not exist_ok:
+                raise
+
+    def rmdir(self, path):
+        """Delete an empty bucket, or with ``dir_markers``, a directory's marker"""
+        path = self._strip_protocol(path).rstrip("/")
+        bucket, _, key = path.partition("/")
+        if not key:
+            gcs_delete_bucket(bucket, **self.kwargs)
+        elif self.dir_markers:
+            gcs_delete(path + "/", **self.kwargs)
+
+    def rm_file(self, path):
+        gcs_delete(self._strip_protocol(path), **self.kwargs)
+
+    def sign(self, path, expiration=100, method="GET", credentials=None, **kwargs):
+        """V4-signed URL for path, valid for ``expiration`` seconds
+
+        Signing needs a service account key file, ``credentials`` or else
+        GOOGLE_APPLICATION_CREDENTIALS.
+        """
+        return gcs_sign(self._strip_protocol(path), expiration=int(expiration), method=method,
+                        credentials=credentials)
+
+
+class RustyGCSFile(AbstractBufferedFile):
+
+    def __init__(self, fs, path, generation=None, **kwargs):
+        # the generation at open time, which every block must be read from
+        self.generation = generation
+        super().__init__(fs, path, **kwargs)
+
+    def _fetch_range(self, start, end):
+        return self.fs.cat_file(self.path, start=start, end=end, generation=self.generation)
@@ -5,7 +5,7 @@ import re
 from copy import copy
 
 import fsspec.utils
-from rfsspec.rfsspec import cat_ranges, get
+from rfsspec.rfsspec import cat_ranges, get, info_many
 
 from fsspec.spec import AbstractFileSystem
 from fsspec.utils import DEFAULT_BLOCK_SIZE
@@ -125,6 +125,23 @@ class RustyHTTPFileSystem(AbstractFileSystem):
     def cat_ranges(self, urls, starts, ends, **kwargs):
         return cat_ranges(urls, starts, ends, **kwargs)
 
+    def info_many(self, urls, concurrency=32, headers=None, **kwargs):
+        """Info of each URL, or None where not found, in order
+
+        Up to ``concurrency`` HEAD requests (GET, where HEAD is not allowed)
+        run at once; size is None if the server does not give it.
+        """
+        out = info_many(list(urls), concurrency=concurrency, headers=headers)
+        for info in out:
+            if info is not None:
+                info["size"] = int(info["size"]) if "size" in info else None
+        return out
+
+    def exists_many(self, urls, concurrency=32, **kwargs):
+        """Whether each URL can be found, in order"""
+        return [info is not None
+                for info in self.info_many(urls, concurrency=concurrency, **kwargs)]
+
     def get_file(self, rpath, lpath, **kwargs):
         get([rpath], [lpath], **kwargs)
 
@@ -1,9 +1,26 @@
-from functools import lru_cache
+import base64
+import datetime
+import os
 from rfsspec.rfsspec import (s3_cat_ranges, s3_info, s3_find, s3_pipe, s3_init_upload,
-                             s3_upload_chunk, s3_complete_upload, s3_ls)
+                             s3_upload_chunk, s3_complete_upload, s3_ls, s3_ls_versions,
+                             s3_get, s3_put, s3_copy, s3_abort_upload, s3_list_uploads,
+                             s3_list_parts, s3_abort_stale_uploads, s3_glob,
+                             s3_list_pages, s3_sign, s3_get_tags, s3_put_tags, s3_checksum,
+                             s3_select, s3_list_buckets, s3_create_bucket, s3_delete_bucket,
+                             s3_delete, s3_info_many, s3_du, s3_restore, s3_get_retention,
+                             s3_put_retention, s3_get_legal_hold, s3_put_legal_hold)
 
+import fsspec.utils
 from fsspec.spec import AbstractFileSystem, AbstractBufferedFile
 
+# storage classes whose objects must be restored before reading
+ARCHIVE_CLASSES = ("GLACIER", "DEEP_ARCHIVE")
+
+# options for new objects, accepted by pipe, put_file and open for writing
+WRITE_OPTIONS = ("content_type", "cache_control", "content_encoding", "metadata", "acl",
+                 "storage_class", "object_lock_mode", "object_lock_retain_until",
+                 "object_lock_legal_hold")
+
 
 class RustyS3FileSystem(AbstractFileSystem):
     """
@@ -19,17 +36,125 @@ class RustyS3FileSystem(AbstractFileSystem):
             region="us-east-1",
             anon=False,
             default_cache_type="readahead",
+            version_aware=False,
+            sse=None,
+            sse_kms_key_id=None,
+            sse_customer_key=None,
+            key=None,
+            secret=None,
+            token=None,
+            role_arn=None,
+            external_id=None,
+            role_session_name=None,
+            web_identity_token_file=None,
+            force_path_style=False,
+            list_version=2,
+            connect_timeout=None,
+            read_timeout=None,
+            operation_timeout=None,
+            max_attempts=None,
+            retry_backoff=None,
+            disable_payload_signing=False,
+            list_parallelism=1,
+            checksum_algorithm=None,
+            verify_checksums=True,
+            dir_markers=False,
             **storage_options,
     ):
         """
+        region: str
+            Region for the client; buckets elsewhere are found automatically
+        key, secret, token: str | None
+            Explicit access key ID, secret key and optional session token;
+            otherwise credentials come from ``profile`` or the environment
+        role_arn: str | None
+            Role to assume, using the credentials above
+        external_id, role_session_name: str | None
+            Passed with the assume-role request
+        web_identity_token_file: str | None
+            With ``role_arn``, assume the role using the OIDC token in this file
+        force_path_style: bool
+            Address buckets as ``endpoint/bucket/key`` rather than by virtual
+            host, as needed by many S3-compatible stores
+        list_version: 1 | 2
+            Use the original ListObjects API for stores without ListObjectsV2
+        connect_timeout, read_timeout, operation_timeout: float | None
+            Timeouts in seconds; the operation timeout covers all retries
+        max_attempts: int | None
+            Total attempts for each request, including the first
+        retry_backoff: float | None
+            Initial backoff in seconds between retries
+        disable_payload_signing: bool
+            Do not hash uploaded data for the request signature; for use
+            over TLS, where it saves a pass over every upload
+        list_parallelism: int
+            Concurrent listings to split large recursive listings (``find``)
+            into, by directory or by ranges of the key space
+        version_aware: bool
+            If True, paths may be of the form "bucket/key?versionId=..." to
+            refer to a specific version of an object, and files opened for
+            reading are pinned to the version current at open time.
+        sse: "AES256" | "aws:kms" | None
+            Server-side encryption for new objects
+        sse_kms_key_id: str | None
+            KMS key to use with ``sse="aws:kms"``, else the bucket default
+        sse_customer_key: bytes | str | None
+            256-bit key for SSE-C; needed for reads as well as writes
+        checksum_algorithm: "CRC32C" | "SHA256" | "MD5" | None
+            Send a checksum with each upload, for S3 to verify and record
+        verify_checksums: bool
+            Check whole-object reads and downloads against the checksum S3
+            recorded (or the MD5 ETag), raising ChecksumError on mismatch
+        dir_markers: bool
+            Have ``mkdir`` write an empty "path/" key, so that a directory
+            exists before it holds any files, and ``rmdir`` remove it
         """
-        self.kwargs = dict(profile=profile, endpoint_url=endpoint_url, requester_pays=requester_pays,
-                           region=region, anon=anon)
+        self.config = dict(profile=profile, endpoint_url=endpoint_url, region=region, key=key,
+                           secret=secret, token=token, role_arn=role_arn, external_id=external_id,
+                           role_session_name=role_session_name,
+                           web_identity_token_file=web_identity_token_file,
+                           force_path_style=force_path_style, list_version=list_version,
+                           connect_timeout=connect_timeout, read_timeout=read_timeout,
+                           operation_timeout=operation_timeout, max_attempts=max_attempts,
+                           retry_backoff=retry_backoff,
+                           disable_payload_signing=disable_payload_signing)
+        self.kwargs = dict(self.config, requester_pays=requester_pays, anon=anon)
+        if isinstance(sse_customer_key, str):
+            sse_customer_key = sse_customer_key.encode()
+        if sse_customer_key is not None:
+            sse_customer_key = base64.b64encode(sse_customer_key).decode()
+        self.sse_kwargs = dict(sse=sse, sse_kms_key_id=sse_kms_key_id,
+                               sse_customer_key=sse_customer_key)
+        self.list_parallelism = list_parallelism
+        self.checksum_algorithm = checksum_algorithm
+        self.verify_checksums = verify_checksums
+        self.dir_markers = dir_markers
         self.default_cache_type = default_cache_type
+        self.version_aware = version_aware
         super().__init__(self, **storage_options)
 
-    def cat_file(self, url, start=None, end=None, **kwargs):
-        return s3_cat_ranges([url], start=[start or 0], end=[end or 0], **self.kwargs)[0]
+    @property
+    def _read_kwargs(self):
+        return dict(self.kwargs, sse_customer_key=self.sse_kwargs["sse_customer_key"])
+
+    @property
+    def _write_kwargs(self):
+        return dict(self.kwargs, checksum_algorithm=self.checksum_algorithm, **self.sse_kwargs)
+
+    def split_path(self, path):
+        """Separate any "?versionId=" from the path, giving (path, version_id)"""
+        path = self._strip_protocol(path)
+        if self.version_aware and "?versionId=" in path:
+            path, version_id = path.split("?versionId=", 1)
+            return path, version_id
+        return path, None
+
+    def cat_file(self, url, start=None, end=None, version_id=None, if_match=None, **kwargs):
+        """Bytes of the object; with if_match, raise FileChangedError if its ETag differs"""
+        url, v = self.split_path(url)
+        return s3_cat_ranges([url], start=[start or 0], end=[end or 0],
+                             version_id=[version_id or v], if_match=[if_match],
+                             verify=self.verify_checksums, **self._read_kwargs)[0]
 
     def cat(self, path, recursive=False, on_error="raise", start=0, end=0, **kwargs):
         paths = [path] if isinstance(path, str) else path
@@ -38,92 +163,467 @@ class RustyS3FileSystem(AbstractFileSystem):
                 or isinstance(path, list)
                 or paths[0] != self._strip_protocol(path)
         ):
+            keys, versions = zip(*[self.split_path(p) for p in paths])
             out = {p: _ for p, _ in zip(paths, s3_cat_ranges(
-                paths, start=[start or 0] * len(path), end=[end or 0] * len(path), **self.kwargs))}
+                list(keys), start=_per_path(start, len(paths)), end=_per_path(end, len(paths)),
+                version_id=list(versions), verify=self.verify_checksums,
+                **self._read_kwargs))}
             return out
         else:
-            return s3_cat_ranges(paths, start=[start or 0], end=[end or 0], **self.kwargs)[0]
+            return self.cat_file(paths[0], start=start, end=end)
 
     def cat_ranges(self, urls, starts, ends, **kwargs):
-        return s3_cat_ranges(urls, start=starts, end=ends, **self.kwargs)
+        keys, versions = zip(*[self.split_path(u) for u in urls])
+        return s3_cat_ranges(list(keys), start=starts, end=ends, version_id=list(versions),
+                             verify=self.verify_checksums, **self._read_kwargs)
 
-    def info(self, path):
-        path = self._strip_protocol(path)
-        info = s3_info(path, **self.kwargs)
+    def info(self, path, version_id=None):
+        path, v = self.split_path(path)
+        info = s3_info(path, version_id=version_id or v, **self._read_kwargs)
         info["name"] = path
-        info["size"] = int(info["size"])
-        info["type"] = "file" if info["size"] or not info["name"].endswith("/") else "directory"
-        info["name"] = path
-        return info
+        return _file_info(info)
+
+    def info_many(self, paths, concurrency=32):
+        """Info of each key, or None where there is no such key, in order
+
+        Up to ``concurrency`` HEAD requests run at once; directories give None.
+        """
+        split = [self.split_path(p) for p in paths]
+        keys = [key for key, _ in split]
+        out = s3_info_many(keys, concurrency=concurrency, version_id=[v for _, v in split],
+                           **self._read_kwargs)
+        for key, info in zip(keys, out):
+            if info is not None:
+                info["name"] = key
+                _file_info(info)
+        return out
+
+    def exists_many(self, paths, concurrency=32):
+        """Whether each path is a key, in order"""
+        return [info is not None for info in self.info_many(paths, concurrency=concurrency)]
+
+    def restore(self, path, days=1, tier="Standard", concurrency=32):
+        """Start restoring archived objects, readable for ``days`` once done
 
-    def pipe(self, path, value=None):
+        path may be one path or a list. tier: "Expedited", "Standard" or
+        "Bulk". Objects already being restored, or not archived, are left
+        alone; follow progress with ``restore_status``.
+        """
+        paths = [path] if isinstance(path, str) else path
+        split = [self.split_path(p) for p in paths]
+        s3_restore([key for key, _ in split], days=days, tier=tier,
+                   version_id=[v for _, v in split], concurrency=concurrency, **self.kwargs)
+
+    def restore_status(self, path):
+        """State of one path, or a list of them, with regard to restoring
+
+        "available" if readable without restoring, "archived" if it must be
+        restored, "in-progress" while being restored, "restored" once it can
+        be read, or None if not found.
+        """
+        paths = [path] if isinstance(path, str) else path
+        out = [_restore_state(info) for info in self.info_many(paths)]
+        return out[0] if isinstance(path, str) else out
+
+    def checksum(self, path, algorithm="CRC32C"):
+        """Base64 checksum of the object's data, "CRC32C", "SHA256" or "MD5"
+
+        The one S3 recorded, if any, else computed without passing the data
+        to python.
+        """
+        path, version_id = self.split_path(path)
+        return s3_checksum(path, algorithm=algorithm, version_id=version_id,
+                           **self._read_kwargs)
+
+    def select(self, path, expression, input_format="csv", output_format="json",
+               iterator=False, **kwargs):
+        """Records of the object matching an SQL expression, using S3 Select
+
+        input_format: "csv", "json" (lines), "json-document" or "parquet"
+        output_format: "csv" or "json"
+        iterator: if True, yield blocks of bytes as they arrive, rather than
+            return all the bytes at the end
+        kwargs: ``compression`` ("GZIP" | "BZIP2"), ``csv_header``
+            ("USE" | "IGNORE" | "NONE") and ``delimiter``
+        """
+        path, _ = self.split_path(path)
+        return s3_select(path, expression, input_format=input_format,
+                         output_format=output_format, iterator=iterator, **kwargs,
+                         **self._read_kwargs)
+
+    def object_version_info(self, path):
+        """All versions and delete markers of keys below the given path"""
         path = self._strip_protocol(path)
-        # pipes only just one for now, for use bu the file-like API
+        out = s3_ls_versions(path, **self.kwargs)
+        for o in out:
+            o["size"] = int(o["size"])
+            o["IsLatest"] = o["IsLatest"] == "true"
+        return out
+
+    def get_file(self, rpath, lpath, version_id=None, **kwargs):
+        rpath, v = self.split_path(rpath)
+        s3_get([rpath], [lpath], version_id=[version_id or v], verify=self.verify_checksums,
+               **self._read_kwargs)
+
+    def get(self, rpath, lpath, recursive=False, **kwargs):
+        if isinstance(rpath, str) and recursive:
+            rpath = self.find(rpath)
+        if not isinstance(rpath, (list, tuple)):
+            rpath = [rpath]
+            lpath = [lpath]
+        lpath = fsspec.utils.other_paths(rpath, lpath)
+        for lp in lpath:
+            # nested keys land in local directories that may not exist yet
+            os.makedirs(os.path.dirname(lp) or ".", exist_ok=True)
+        keys, versions = zip(*[self.split_path(p) for p in rpath])
+        s3_get(list(keys), lpath, version_id=list(versions), verify=self.verify_checksums,
+               **self._read_kwargs)
+
+    def put_file(self, lpath, rpath, **kwargs):
+        rpath = self._strip_protocol(rpath)
+        return s3_put([lpath], [rpath], **self._write_kwargs, **_write_options(kwargs))[0]
+
+    def cp_file(self, path1, path2, **kwargs):
+        path1 = self._strip_protocol(path1)
+        path2 = self._strip_protocol(path2)
+        return s3_copy(path1, path2, **self._write_kwargs)
+
+    def sign(self, path, expiration=100, method="GET", **kwargs):
+        """Presigned URL for path, valid for ``expiration`` seconds
+
+        method may be "GET", "HEAD", "PUT" or "DELETE".
+        """
+        path, version_id = self.split_path(path)
+        return s3_sign(path, expiration=float(expiration), method=method, version_id=version_id,
+                       requester_pays=self.kwargs["requester_pays"], **self.config)
+
+    def pipe(self, path, value=None, if_none_match=None, if_match=None, raise_on_error=True,
+             concurrency=32, **kwargs):
+        """Write bytes to path, or each of a dict or iterable of (path, bytes)
+
+        Returns a dict of path: {"ETag", "VersionId"} in input order, with the
+        exception in place of any failed upload unless raise_on_error. Up to
+        ``concurrency`` uploads run at once, and an iterable is only consumed
+        as they complete.
+
+        if_none_match="*" writes only new keys, and if_match only replaces keys
+        with that ETag; any other key fails with PreconditionFailedError, as
+        its result or raised.
+
+        content_type (guessed from the extension if not given), cache_control,
+        content_encoding, metadata (dict), acl and storage_class apply to the
+        new objects, as do object_lock_mode ("GOVERNANCE" | "COMPLIANCE") with
+        object_lock_retain_until (datetime or epoch seconds), and
+        object_lock_legal_hold (bool), in buckets with Object Lock.
+        """
         if isinstance(path, str):
-            path = {path: value}
-        kw = self.kwargs.copy()
-        kw.pop("anon")
-        kw.pop("requester_pays")
-        s3_pipe(path, **kw)
+            data = {self._strip_protocol(path): value}
+        elif isinstance(path, dict):
+            data = {self._strip_protocol(p): v for p, v in path.items()}
+        else:
+            data = ((self._strip_protocol(p), v) for p, v in path)
+        return s3_pipe(data, if_none_match=if_none_match, if_match=if_match,
+                       concurrency=concurrency, raise_on_error=raise_on_error,
+                       **self._write_kwargs, **_write_options(kwargs))
 
-    def _open(self, path, mode="rb", **kwargs):
-        path = self._strip_protocol(path)
-        size = int(self.info(path)["size"]) if "r" in mode else None
+    def get_tags(self, path):
+        """Tags of the object at path, as a dict"""
+        path, version_id = self.split_path(path)
+        return s3_get_tags(path, version_id=version_id, **self.config)
+
+    def put_tags(self, path, tags, mode="o"):
+        """Set tags on the object at path
+
+        mode: "o" to replace all tags, "m" to merge with the existing ones
+        """
+        if mode == "m":
+            tags = dict(self.get_tags(path), **tags)
+        elif mode != "o":
+            raise ValueError("mode must be 'o' or 'm'")
+        path, version_id = self.split_path(path)
+        s3_put_tags(path, tags, version_id=version_id, **self.config)
+
+    def get_retention(self, path):
+        """Object Lock retention of the object, as a dict of Mode and
+        RetainUntilDate, or empty if it has none"""
+        path, version_id = self.split_path(path)
+        return s3_get_retention(path, version_id=version_id, **self.config)
+
+    def put_retention(self, path, mode=None, retain_until=None, bypass_governance=False):
+        """Set Object Lock retention of the object, or remove it if neither given
+
+        mode: "GOVERNANCE" | "COMPLIANCE"
+        retain_until: datetime or epoch seconds
+        bypass_governance: needed to shorten or remove GOVERNANCE retention
+        """
+        path, version_id = self.split_path(path)
+        s3_put_retention(path, mode=mode, retain_until=_epoch(retain_until),
+                         version_id=version_id, bypass_governance=bypass_governance,
+                         **self.config)
+
+    def get_legal_hold(self, path):
+        """Whether the object is under an Object Lock legal hold"""
+        path, version_id = self.split_path(path)
+        return s3_get_legal_hold(path, version_id=version_id, **self.config)
+
+    def put_legal_hold(self, path, on=True):
+        """Place (or with on=False, lift) a legal hold on the object"""
+        path, version_id = self.split_path(path)
+        s3_put_legal_hold(path, on, version_id=version_id, **self.config)
+
+    def list_multipart_uploads(self, path):
+        """In-progress multipart uploads with keys below path"""
+        return s3_list_uploads(self._strip_protocol(path), **self.config)
+
+    def list_parts(self, path, mpu):
+        """Parts uploaded so far to the given multipart upload"""
+        out = s3_list_parts(self._strip_protocol(path), mpu, **self.config)
+        for o in out:
+            o["PartNumber"] = int(o["PartNumber"])
+            o["size"] = int(o["size"])
+        return out
+
+    def abort_multipart_upload(self, path, mpu):
+        s3_abort_upload(self._strip_protocol(path), mpu, **self.kwargs)
+
+    def clear_multipart_uploads(self, path, older_than=0):
+        """Abort uploads below path started more than older_than seconds ago
+
+        Returns the uploads that were aborted.
+        """
+        return s3_abort_stale_uploads(self._strip_protocol(path), older_than,
+                                      **self.config)
+
+    def _open(self, path, mode="rb", version_id=None, **kwargs):
+        path, v = self.split_path(path)
+        version_id = version_id or v
+        size = None
+        etag = None
+        if "r" in mode:
+            info = self.info(path, version_id=version_id)
+            size = info["size"]
+            etag = info.get("ETag") or None
+            if self.version_aware:
+                # pin reads to the version current at open time
+                version_id = info.get("VersionId", version_id)
         if "cache_type" not in kwargs:
             kwargs["cache_type"] = self.default_cache_type
-        return RustyS3File(self, path, mode=mode, size=size, **kwargs)
+        return RustyS3File(self, path, mode=mode, size=size, version_id=version_id, etag=etag,
+                           **kwargs)
 
-    def find(self, path, detail=False):
-        out = s3_find(path, **self.kwargs)
+    def find(self, path, maxdepth=None, withdirs=False, detail=False, start_after=None,
+             **kwargs):
+        """Files (and optionally directories) below path
+
+        start_after: str | None
+            Resume a listing after this name, e.g., the last one returned
+        """
+        path = self._strip_protocol(path)
+        out = s3_find(path, maxdepth=maxdepth, withdirs=withdirs, start_after=start_after,
+                      parallelism=self.list_parallelism, **self.kwargs)
+        for o in out:
+            o["size"] = int(o["size"])
         if detail:
-            return out
+            return {o["name"]: o for o in out}
+        return [o["name"] for o in out]
+
+    def du(self, path, total=True, maxdepth=None, **kwargs):
+        """Total size of the files below path or, if not total, a dict of the
+        total in each directory below it, path included
+
+        maxdepth: count only files up to this many levels below path
+        """
+        return s3_du(self._strip_protocol(path), total=total, maxdepth=maxdepth,
+                     parallelism=self.list_parallelism, **self.kwargs)
+
+    def glob(self, path, maxdepth=None, detail=False, **kwargs):
+        out = s3_glob(self._strip_protocol(path), maxdepth=maxdepth, **self.kwargs)
+        for o in out:
+            o["size"] = int(o["size"])
+        if detail:
+            return {o["name"]: o for o in out}
         return [o["name"] for o in out]
 
-    def ls(self, path, ):
+    def walk(self, path, maxdepth=None, topdown=True, detail=False, **kwargs):
+        """As os.walk, listing one directory at a time with delimited listings
+
+        Only directories visited are listed: none deeper than ``maxdepth``, nor
+        any the caller prunes from ``dirs`` when topdown.
+        """
+        if maxdepth is not None and maxdepth < 1:
+            raise ValueError("maxdepth must be at least 1")
+        path = self._strip_protocol(path).rstrip("/")
+        dirs, files = {}, {}
+        for info in self.ls_iter(path):
+            name = info["name"].rstrip("/")
+            if name == path:
+                # the directory's own marker
+                continue
+            base = name.rsplit("/", 1)[-1]
+            (dirs if info["type"] == "directory" else files)[base] = info
+        if not dirs and not files:
+            return
+        if not detail:
+            dirs, files = list(dirs), list(files)
+        if topdown:
+            # caller may prune dirs
+            yield path, dirs, files
+        if maxdepth is None or maxdepth > 1:
+            for d in list(dirs):
+                if d in dirs:
+                    yield from self.walk(f"{path}/{d}", None if maxdepth is None else maxdepth - 1,
+                                         topdown=topdown, detail=detail)
+        if not topdown:
+            yield path, dirs, files
+
+    def ls(self, path, detail=True, **kwargs):
         path = self._strip_protocol(path)
-        out = s3_ls(path, **self.kwargs)
+        if path:
+            out = s3_ls(path, **self.kwargs)
+        else:
+            out = s3_list_buckets(**self.config)
         for o in out:
             o["size"] = int(o["size"])
-            if o["size"] == 0 and o["name"].endswith("/"):
-                o["type"] = "directory"
-        return out
+        if detail:
+            return list(out)
+        return [o["name"] for o in out]
+
+    def ls_iter(self, path, recursive=False):
+        """Yield entries of ``ls(path)`` (or ``find(path)`` if recursive) as they arrive"""
+        for page in s3_list_pages(self._strip_protocol(path), recursive=recursive, **self.kwargs):
+            for o in page:
+                o["size"] = int(o["size"])
+                yield o
 
     def isdir(self, path):
-        path = self._strip_protocol(path)
-        path = path.rstrip("/") + "/"
         try:
-            out = self.ls(path)
-        except:
-            out = []
-        return len(out) > 1 or any(_["name"] == path for _ in out)
+            return self.info(path)["type"] == "directory"
+        except FileNotFoundError:
+            return False
+
+    def mkdir(self, path, create_parents=True, region=None, acl=None, **kwargs):
+        """Create a bucket, or a directory in one
+
+        A new bucket goes in ``region``, else the filesystem's, with the
+        canned ``acl``, if given; FileExistsError if it already exists. Below
+        the bucket, directories only exist while they hold files, unless the
+        filesystem has ``dir_markers``.
+        """
+        path = self._strip_protocol(path).rstrip("/")
+        bucket, _, key = path.partition("/")
+        if not key or (create_parents and not self._bucket_exists(bucket)):
+            config = dict(self.config, region=region or self.config["region"])
+            try:
+                s3_create_bucket(bucket, acl=acl, **config)
+            except FileExistsError:
+                if not key:
+                    raise
+        if key and self.dir_markers:
+            self.pipe(path + "/", b"")
+
+    def _bucket_exists(self, bucket):
+        try:
+            next(iter(s3_list_pages(bucket, **self.kwargs)), None)
+        except FileNotFoundError:
+            return False
+        except PermissionError:
+            # someone else's bucket, which we may not list
+            return True
+        return True
+
+    def makedirs(self, path, exist_ok=False):
+        try:
+            self.mkdir(path, create_parents=True)
+        except FileExistsError:
+            if not exist_ok:
+                raise
+
+    def rmdir(self, path):
+        """Delete an empty bucket, or with ``dir_markers``, a directory's marker"""
+        path = self._strip_protocol(path).rstrip("/")
+        bucket, _, key = path.partition("/")
+        if not key:
+            s3_delete_bucket(bucket, **self.config)
+        elif self.dir_markers:
+            s3_delete(path + "/", **self.kwargs)
+
+    def rm_file(self, path):
+        path, version_id = self.split_path(path)
+        s3_delete(path, version_id=version_id, **self.kwargs)
 
 
 class RustyS3File(AbstractBufferedFile):
     DEFAULT_BLOCK_SIZE = 50*2**20  # TODO: enforce 5MB minimum?
     mpu = None
 
+    def __init__(self, fs, path, version_id=None, etag=None, if_none_match=None, if_match=None,
+                 **kwargs):
+        # for writing, headers, metadata and placement of the new object
+        self.write_options = {k: kwargs.pop(k) for k in WRITE_OPTIONS if k in kwargs}
+        # for reading, the version to fetch; for writing, set to the new version on commit
+        self.version_id = version_id
+        # for reading, the ETag at open time, which every block must match
+        self.etag = etag
+        # for writing, conditions for committing the new object
+        self.conditions = dict(if_none_match=if_none_match, if_match=if_match)
+        super().__init__(fs, path, **kwargs)
+
     def _fetch_range(self, start, end):
-        return self.fs.cat_file(self.path, start=start, end=end)
+        return self.fs.cat_file(self.path, start=start, end=end, version_id=self.version_id,
+                                if_match=self.etag)
+
+    def discard(self):
+        """Abandon writing, removing any parts already uploaded"""
+        if self.mpu is not None:
+            self.fs.abort_multipart_upload(self.path, self.mpu)
+            self.mpu = None
+        if self.writable():
+            self.buffer = None
+            self.closed = True
+
+    def __del__(self):
+        # a writer dropped without close() is abandoned: abort, not commit
+        if not self.closed and self.writable():
+            self.discard()
+
+    def __exit__(self, exc_type, *args):
+        if exc_type is not None and self.writable():
+            # do not commit a partial file
+            self.discard()
+        else:
+            self.close()
 
     def _upload_chunk(self, final=False):
-        kw = self.fs.kwargs.copy()
-        kw.pop("requester_pays")
-        kw.pop("anon")
+        kw = self.fs.kwargs
+        algorithm = self.fs.checksum_algorithm
+        if algorithm is None and any(k.startswith("object_lock") for k in self.write_options):
+            # S3 only takes Object Lock settings with a checksum of the data
+            algorithm = "MD5"
+        chunk_kw = dict(kw, sse_customer_key=self.fs.sse_kwargs["sse_customer_key"],
+                        checksum_algorithm=algorithm)
         if final:
             if self.mpu is None:
                 # one-shot upload
-                self.fs.pipe(self.path, self.buffer.getvalue())
+                out = self.fs.pipe(self.path, self.buffer.getvalue(), **self.conditions,
+                                   **self.write_options)[self.path]
+                self.version_id = out.get("VersionId")
             else:
                 part = len(self.parts) + 1
-                self.parts[part] = s3_upload_chunk(self.path, self.mpu, self.buffer.getbuffer(), part, **kw)
-                s3_complete_upload(self.path, self.mpu, self.parts, **kw)
+                self.parts[part] = s3_upload_chunk(self.path, self.mpu, self.buffer.getbuffer(), part,
+                                                   **chunk_kw)
+                self.version_id = s3_complete_upload(
+                    self.path, self.mpu, self.parts, checksum_algorithm=algorithm,
+                    **self.conditions, **kw)
         elif self.buffer.tell() > self.blocksize:
             if self.mpu is None:
-                self.mpu = s3_init_upload(self.path, **kw)
-                self.parts = {1: s3_upload_chunk(self.path, self.mpu, self.buffer.getbuffer(), 1, **kw)}
+                self.mpu = s3_init_upload(self.path, **self.fs._write_kwargs,
+                                          **_write_options(self.write_options))
+                self.parts = {1: s3_upload_chunk(self.path, self.mpu, self.buffer.getbuffer(), 1,
+                                                 **chunk_kw)}
             else:
                 part = len(self.parts) + 1
-                self.parts[part] = s3_upload_chunk(self.path, self.mpu,self.buffer.getbuffer(), part, **kw)
+                self.parts[part] = s3_upload_chunk(self.path, self.mpu,self.buffer.getbuffer(), part,
+                                                   **chunk_kw)
         else:
             # nothing to do
             return False
@@ -131,7 +631,38 @@ class RustyS3File(AbstractBufferedFile):
         return True
 
 
-@lru_cache()
-def get_bucket_region(bucket):
-    import requests
-    return requests.head(f"https://{bucket}.s3.amazonaws.com").headers["x-amz-bucket-region"]
\ No newline at end of file
+def _file_info(info):
+    info["size"] = int(info["size"])
+    if "RestoreInProgress" in info:
+        info["RestoreInProgress"] = info["RestoreInProgress"] == "true"
+    return info
+
+
+def _restore_state(info):
+    if info is None:
+        return None
+    if "RestoreInProgress" in info:
+        return "in-progress" if info["RestoreInProgress"] else "restored"
+    if info.get("StorageClass") in ARCHIVE_CLASSES or "ArchiveStatus" in info:
+        return "archived"
+    return "available"
+
+
+def _per_path(value, n):
+    """start or end offsets for n paths, from one value for all or a list"""
+    if isinstance(value, (list, tuple)):
+        if len(value) != n:
+            raise ValueError("Need one start/end per path")
+        return [v or 0 for v in value]
+    return [value or 0] * n
+
+
+def _epoch(t):
+    return t.timestamp() if isinstance(t, datetime.datetime) else t
+
+
+def _write_options(kwargs):
+    out = {k: v for k, v in kwargs.items() if k in WRITE_OPTIONS}
+    if "object_lock_retain_until" in out:
+        out["object_lock_retain_until"] = _epoch(out["object_lock_retain_until"])
+    return out
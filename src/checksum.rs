@@ -0,0 +1,85 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use md5::{Digest, Md5};
+use sha2::Sha256;
+
+/// A data integrity check that object stores can verify and record
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Algorithm {
+    Crc32c,
+    Sha256,
+    Md5,
+}
+
+impl Algorithm {
+    /// From a name such as "CRC32C", "sha256" or "md5"
+    pub fn from_name(name: &str) -> Option<Algorithm> {
+        match name.to_uppercase().as_str() {
+            "CRC32C" => Some(Algorithm::Crc32c),
+            "SHA256" => Some(Algorithm::Sha256),
+            "MD5" => Some(Algorithm::Md5),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Algorithm::Crc32c => "CRC32C",
+            Algorithm::Sha256 => "SHA256",
+            Algorithm::Md5 => "MD5",
+        }
+    }
+
+    pub fn hasher(&self) -> Hasher {
+        match self {
+            Algorithm::Crc32c => Hasher::Crc32c(0),
+            Algorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            Algorithm::Md5 => Hasher::Md5(Md5::new()),
+        }
+    }
+}
+
+/// Incremental computation of a checksum, for data arriving in chunks
+pub enum Hasher {
+    Crc32c(u32),
+    Sha256(Sha256),
+    Md5(Md5),
+}
+
+impl Hasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Crc32c(crc) => *crc = crc32c::crc32c_append(*crc, data),
+            Hasher::Sha256(h) => h.update(data),
+            Hasher::Md5(h) => h.update(data),
+        }
+    }
+
+    /// The checksum, base64-encoded as in HTTP headers
+    pub fn finish(self) -> String {
+        match self {
+            Hasher::Crc32c(crc) => STANDARD.encode(crc.to_be_bytes()),
+            Hasher::Sha256(h) => STANDARD.encode(h.finalize()),
+            Hasher::Md5(h) => STANDARD.encode(h.finalize()),
+        }
+    }
+}
+
+pub fn checksum(algorithm: Algorithm, data: &[u8]) -> String {
+    let mut hasher = algorithm.hasher();
+    hasher.update(data);
+    hasher.finish()
+}
+
+/// A hex digest, such as a single-part upload's ETag, in base64; None if it
+/// is not hex
+pub fn hex_to_base64(hex: &str) -> Option<String> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    let bytes: Option<Vec<u8>> = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect();
+    bytes.map(|b| STANDARD.encode(b))
+}
@@ -0,0 +1,58 @@
+use pyo3::prelude::*;
+use std::collections::HashMap;
+
+/// Disk usage of the files below a root: their total size and, if wanted,
+/// the total within each directory, the root included
+pub struct Usage {
+    root: String,
+    maxdepth: Option<usize>,
+    pub total: u64,
+    pub dirs: Option<HashMap<String, u64>>,
+}
+
+impl Usage {
+    /// root is the path as given, e.g., "bucket/data", and names the
+    /// directories; files more than maxdepth levels below it are not counted
+    pub fn new(root: &str, maxdepth: Option<usize>, per_dir: bool) -> Usage {
+        let root = root.trim_end_matches('/').to_string();
+        let dirs = per_dir.then(|| HashMap::from([(root.clone(), 0)]));
+        Usage { root, maxdepth, total: 0, dirs }
+    }
+
+    /// Count the root itself, when it is a file rather than a directory
+    pub fn add_root(&mut self, size: u64) {
+        self.total += size;
+        if let Some(dirs) = &mut self.dirs {
+            *dirs.get_mut(&self.root).unwrap() += size;
+        }
+    }
+
+    /// Count a file, by its key relative to the root, e.g., "a/b/file"
+    pub fn add(&mut self, rel: &str, size: u64) {
+        if rel.is_empty() || rel.ends_with('/') {
+            // the root itself, or a directory marker
+            return;
+        }
+        if self.maxdepth.is_some_and(|d| rel.split('/').count() > d) {
+            return;
+        }
+        self.total += size;
+        if let Some(dirs) = &mut self.dirs {
+            *dirs.get_mut(&self.root).unwrap() += size;
+            for (i, _) in rel.match_indices('/') {
+                let dir = format!("{}/{}", self.root, &rel[..i]);
+                *dirs.entry(dir).or_insert(0) += size;
+            }
+        }
+    }
+}
+
+/// The total, or the totals by directory
+impl IntoPy<PyObject> for Usage {
+    fn into_py(self, py: Python) -> PyObject {
+        match self.dirs {
+            Some(dirs) => dirs.into_py(py),
+            None => self.total.into_py(py),
+        }
+    }
+}
@@ -0,0 +1,451 @@
+use futures::future::join_all;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+
+/// One element of a compiled glob pattern
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Char(char),
+    /// "?": any one character except "/"
+    Any,
+    /// "*": any characters except "/"
+    Star,
+    /// "**": any characters
+    GlobStar,
+    /// "**/": zero or more whole path segments
+    GlobStarSlash,
+    /// "[...]": inclusive character ranges, and whether negated
+    Class(Vec<(char, char)>, bool),
+}
+
+/// A glob over the keys of one bucket, as in "bucket/data/*/part-?.csv"
+///
+/// Supports `*` and `?` within a path segment, `[abc]`, `[a-z]` and `[!a-z]`
+/// classes and `**` for any depth, such that "a/**/b" also matches "a/b".
+#[derive(Debug)]
+pub struct Pattern {
+    key: String,
+    tokens: Vec<Token>,
+}
+
+fn tokenize(pattern: &str) -> Vec<Token> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                if chars.get(i + 2) == Some(&'/') {
+                    tokens.push(Token::GlobStarSlash);
+                    i += 3;
+                } else {
+                    tokens.push(Token::GlobStar);
+                    i += 2;
+                }
+                continue;
+            }
+            '*' => tokens.push(Token::Star),
+            '?' => tokens.push(Token::Any),
+            '[' => match parse_class(&chars[i + 1..]) {
+                Some((token, used)) => {
+                    tokens.push(token);
+                    i += used + 1;
+                    continue;
+                }
+                // unclosed, so literal
+                None => tokens.push(Token::Char('[')),
+            },
+            c => tokens.push(Token::Char(c)),
+        }
+        i += 1;
+    }
+    tokens
+}
+
+/// Parse the inside of "[...]", returning the class and characters used,
+/// including the closing "]"
+fn parse_class(chars: &[char]) -> Option<(Token, usize)> {
+    let negated = matches!(chars.first(), Some('!') | Some('^'));
+    let mut i = negated as usize;
+    let mut ranges: Vec<(char, char)> = Vec::new();
+    let start = i;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == ']' && i > start {
+            return Some((Token::Class(ranges, negated), i + 1));
+        }
+        if chars.get(i + 1) == Some(&'-')
+            && chars.get(i + 2).is_some_and(|e| *e != ']')
+        {
+            ranges.push((c, chars[i + 2]));
+            i += 3;
+        } else {
+            ranges.push((c, c));
+            i += 1;
+        }
+    }
+    None
+}
+
+fn matches(tokens: &[Token], s: &[char]) -> bool {
+    match tokens.split_first() {
+        None => s.is_empty(),
+        Some((Token::Char(c), rest)) => {
+            s.first() == Some(c) && matches(rest, &s[1..])
+        }
+        Some((Token::Any, rest)) => {
+            !s.is_empty() && s[0] != '/' && matches(rest, &s[1..])
+        }
+        Some((Token::Class(ranges, negated), rest)) => {
+            !s.is_empty()
+                && s[0] != '/'
+                && ranges.iter().any(|(a, b)| (*a..=*b).contains(&s[0]))
+                    != *negated
+                && matches(rest, &s[1..])
+        }
+        Some((Token::Star, rest)) => (0..=s.len())
+            .take_while(|&i| i == 0 || s[i - 1] != '/')
+            .any(|i| matches(rest, &s[i..])),
+        Some((Token::GlobStar, rest)) => {
+            (0..=s.len()).any(|i| matches(rest, &s[i..]))
+        }
+        Some((Token::GlobStarSlash, rest)) => {
+            matches(rest, s)
+                || (1..=s.len())
+                    .any(|i| s[i - 1] == '/' && matches(rest, &s[i..]))
+        }
+    }
+}
+
+fn has_magic(s: &str) -> bool {
+    s.contains(['*', '?', '['])
+}
+
+impl Pattern {
+    /// Compile the key part of a pattern, after the bucket
+    pub fn new(key: &str) -> Pattern {
+        let key = key.trim_matches('/').to_string();
+        let tokens = tokenize(&key);
+        Pattern { key, tokens }
+    }
+
+    pub fn is_match(&self, key: &str) -> bool {
+        let chars: Vec<char> = key.chars().collect();
+        matches(&self.tokens, &chars)
+    }
+
+    /// The literal part of the pattern before any wildcard; all matches
+    /// start with this
+    pub fn prefix(&self) -> &str {
+        let end = self.key.find(['*', '?', '[']).unwrap_or(self.key.len());
+        &self.key[..end]
+    }
+
+    /// The directory of the literal prefix, ending in "/" (or empty), below
+    /// which depths are counted
+    pub fn root(&self) -> &str {
+        let prefix = self.prefix();
+        &prefix[..prefix.rfind('/').map_or(0, |i| i + 1)]
+    }
+
+    /// Whether matches may be any depth below the prefix
+    pub fn recursive(&self) -> bool {
+        self.tokens
+            .iter()
+            .any(|t| matches!(t, Token::GlobStar | Token::GlobStarSlash))
+    }
+}
+
+/// A listing of the keys starting with a prefix: info dicts for files, which
+/// must include "name" as "bucket/key", and common prefixes ending in "/"
+pub type Listing = (Vec<HashMap<String, String>>, Vec<String>);
+
+fn dir_info(bucket: &str, key: &str) -> HashMap<String, String> {
+    let mut h: HashMap<String, String> = HashMap::new();
+    h.insert("name".to_string(), format!("{}/{}", bucket, key));
+    h.insert("size".to_string(), "0".to_string());
+    h.insert("type".to_string(), "directory".to_string());
+    h
+}
+
+fn key_of<'a>(bucket: &str, info: &'a HashMap<String, String>) -> &'a str {
+    let name = info["name"].as_str();
+    name.strip_prefix(bucket)
+        .and_then(|k| k.strip_prefix('/'))
+        .unwrap_or(name)
+}
+
+/// Files and directories in the bucket matching the pattern, sorted by name.
+///
+/// `list(prefix, delimited)` lists all keys starting with prefix, with the
+/// "/" delimiter if `delimited`. Patterns without "**" are matched one
+/// segment at a time with delimited listings below the literal prefix;
+/// others with one flat listing, inferring directories from the keys, or
+/// with `maxdepth`, delimited listings down to that many levels below the
+/// prefix's directory. Matches deeper than `maxdepth` are not returned.
+pub async fn glob<F, Fut, E>(
+    bucket: &str, pattern: &Pattern, maxdepth: Option<usize>, list: F,
+) -> Result<Vec<HashMap<String, String>>, E>
+where
+    F: Fn(String, bool) -> Fut,
+    Fut: Future<Output = Result<Listing, E>>,
+{
+    let mut output: Vec<HashMap<String, String>> = Vec::new();
+    let root = pattern.root();
+    if let (true, Some(maxdepth)) = (pattern.recursive(), maxdepth) {
+        // first the keys in root starting with the literal prefix, then
+        // whole directories, level by level
+        let mut level: Vec<String> = vec![pattern.prefix().to_string()];
+        for depth in 1..=maxdepth {
+            let listings =
+                join_all(level.iter().map(|p| list(p.clone(), true)));
+            let mut next: Vec<String> = Vec::new();
+            for listing in listings.await {
+                let (files, prefixes) = listing?;
+                for info in files {
+                    let key = key_of(bucket, &info);
+                    if !key.ends_with('/') && pattern.is_match(key) {
+                        output.push(info);
+                    }
+                }
+                for p in prefixes {
+                    let dir = &p[..p.len() - 1];
+                    if pattern.is_match(dir) {
+                        output.push(dir_info(bucket, dir));
+                    }
+                    if depth < maxdepth {
+                        next.push(p);
+                    }
+                }
+            }
+            level = next;
+            if level.is_empty() {
+                break;
+            }
+        }
+    } else if pattern.recursive() {
+        let (files, _) = list(pattern.prefix().to_string(), false).await?;
+        // directories can only match below the literal prefix's own
+        let base = root.len();
+        let mut dirs: HashSet<String> = HashSet::new();
+        for info in files {
+            let key = key_of(bucket, &info);
+            for (i, _) in key.match_indices('/').filter(|(i, _)| *i >= base) {
+                let dir = &key[..i];
+                if !dirs.contains(dir) && pattern.is_match(dir) {
+                    dirs.insert(dir.to_string());
+                    output.push(dir_info(bucket, dir));
+                }
+            }
+            if !key.ends_with('/') && pattern.is_match(key) {
+                output.push(info);
+            }
+        }
+    } else {
+        let segments: Vec<&str> =
+            pattern.key.split('/').filter(|s| !s.is_empty()).collect();
+        // leading literal segments are listed past directly
+        let first = segments
+            .iter()
+            .position(|s| has_magic(s))
+            .unwrap_or(segments.len().saturating_sub(1));
+        let mut level: Vec<String> = vec![segments[..first]
+            .iter()
+            .map(|s| format!("{}/", s))
+            .collect()];
+        for (depth, segment) in segments.iter().enumerate().skip(first) {
+            let last = depth == segments.len() - 1;
+            let seg = Pattern::new(segment);
+            let listings =
+                join_all(level.iter().map(|dir| {
+                    list(format!("{}{}", dir, seg.prefix()), true)
+                }))
+                .await;
+            let mut next: Vec<String> = Vec::new();
+            for (dir, listing) in level.iter().zip(listings) {
+                let (files, prefixes) = listing?;
+                if last {
+                    for info in files {
+                        let key = key_of(bucket, &info);
+                        if !key.ends_with('/')
+                            && seg.is_match(&key[dir.len()..])
+                        {
+                            output.push(info);
+                        }
+                    }
+                }
+                for p in prefixes {
+                    let name = p[dir.len()..].trim_end_matches('/');
+                    if seg.is_match(name) {
+                        if last {
+                            output.push(dir_info(bucket, &p[..p.len() - 1]));
+                        } else {
+                            next.push(p);
+                        }
+                    }
+                }
+            }
+            level = next;
+            if level.is_empty() {
+                break;
+            }
+        }
+    }
+    if let Some(maxdepth) = maxdepth {
+        output.retain(|info| {
+            let key = key_of(bucket, info);
+            key.get(root.len()..).map_or(0, |k| k.matches('/').count())
+                < maxdepth
+        });
+    }
+    output.sort_by(|a, b| a["name"].cmp(&b["name"]));
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    fn is_match(pattern: &str, key: &str) -> bool {
+        Pattern::new(pattern).is_match(key)
+    }
+
+    fn file(bucket: &str, key: &str) -> HashMap<String, String> {
+        let mut h: HashMap<String, String> = HashMap::new();
+        h.insert("name".to_string(), format!("{}/{}", bucket, key));
+        h.insert("type".to_string(), "file".to_string());
+        h
+    }
+
+    /// A delimited or flat listing of `keys` below prefix, as a store would
+    fn listing(keys: &[&str], prefix: &str, delimited: bool) -> Listing {
+        let mut files: Vec<HashMap<String, String>> = Vec::new();
+        let mut prefixes: Vec<String> = Vec::new();
+        for key in keys.iter().filter(|k| k.starts_with(prefix)) {
+            // a delimited listing cuts at the first "/" after the directory
+            let dir = prefix.rfind('/').map_or(0, |i| i + 1);
+            match key[dir..].find('/') {
+                Some(i) if delimited && dir + i + 1 < key.len() => {
+                    let p = key[..dir + i + 1].to_string();
+                    if !prefixes.contains(&p) {
+                        prefixes.push(p);
+                    }
+                }
+                _ => files.push(file("bucket", key)),
+            }
+        }
+        (files, prefixes)
+    }
+
+    fn names(
+        keys: &[&str], pattern: &str, maxdepth: Option<usize>,
+    ) -> Vec<String> {
+        let list = |prefix: String, delimited: bool| async move {
+            Ok::<Listing, ()>(listing(keys, &prefix, delimited))
+        };
+        let found =
+            block_on(glob("bucket", &Pattern::new(pattern), maxdepth, list));
+        found
+            .unwrap()
+            .iter()
+            .map(|info| format!("{}:{}", info["name"], info["type"]))
+            .collect()
+    }
+
+    #[test]
+    fn tokens() {
+        assert_eq!(
+            tokenize("a*?"),
+            vec![Token::Char('a'), Token::Star, Token::Any]
+        );
+        assert_eq!(
+            tokenize("**/x**"),
+            vec![Token::GlobStarSlash, Token::Char('x'), Token::GlobStar]
+        );
+        // unclosed "[" is literal
+        assert_eq!(
+            tokenize("[ab"),
+            vec![Token::Char('['), Token::Char('a'), Token::Char('b')]
+        );
+    }
+
+    #[test]
+    fn classes() {
+        let chars: Vec<char> = "!a-z]".chars().collect();
+        assert_eq!(
+            parse_class(&chars),
+            Some((Token::Class(vec![('a', 'z')], true), 5))
+        );
+        // a leading "]" is a member, and a trailing "-" is literal
+        let chars: Vec<char> = "]a-]".chars().collect();
+        assert_eq!(
+            parse_class(&chars),
+            Some((
+                Token::Class(vec![(']', ']'), ('a', 'a'), ('-', '-')], false),
+                4
+            ))
+        );
+        assert_eq!(parse_class(&"abc".chars().collect::<Vec<char>>()), None);
+
+        assert!(is_match("[!a-z]x", "1x"));
+        assert!(!is_match("[!a-z]x", "bx"));
+        assert!(is_match("[]]", "]"));
+        assert!(!is_match("[a-z]", "/"));
+        assert!(is_match("a[b", "a[b"));
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(is_match("a/*.csv", "a/x.csv"));
+        assert!(is_match("a/*.csv", "a/.csv"));
+        assert!(!is_match("a/*.csv", "a/b/x.csv"));
+        assert!(is_match("part-?", "part-1"));
+        assert!(!is_match("part-?", "part-"));
+        assert!(!is_match("a?b", "a/b"));
+        assert!(is_match("a/**", "a/b/c"));
+        // "**/" may match no segments at all
+        assert!(is_match("a/**/b", "a/b"));
+        assert!(is_match("a/**/b", "a/x/y/b"));
+        assert!(!is_match("a/**/b", "a/xb"));
+    }
+
+    #[test]
+    fn prefix_and_root() {
+        let p = Pattern::new("/data/2023/*/part-?.csv");
+        assert_eq!(p.prefix(), "data/2023/");
+        assert_eq!(p.root(), "data/2023/");
+        assert!(!p.recursive());
+
+        let p = Pattern::new("data/ab*/x");
+        assert_eq!(p.prefix(), "data/ab");
+        assert_eq!(p.root(), "data/");
+
+        let p = Pattern::new("data**");
+        assert_eq!(p.root(), "");
+        assert!(p.recursive());
+        assert!(Pattern::new("a/**/b").recursive());
+    }
+
+    #[test]
+    fn globbing() {
+        let keys = ["root/", "root/a.csv", "root/b.txt", "root/sub/c.csv"];
+        // the "root/" directory marker is not a file in it
+        assert_eq!(
+            names(&keys, "root/*", None),
+            vec![
+                "bucket/root/a.csv:file",
+                "bucket/root/b.txt:file",
+                "bucket/root/sub:directory",
+            ]
+        );
+        assert_eq!(
+            names(&keys, "root/**.csv", None),
+            vec!["bucket/root/a.csv:file", "bucket/root/sub/c.csv:file",]
+        );
+        assert_eq!(
+            names(&keys, "root/**.csv", Some(1)),
+            vec!["bucket/root/a.csv:file"]
+        );
+    }
+}
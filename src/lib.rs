@@ -1,23 +1,72 @@
+mod checksum;
+mod du;
+mod glob;
 mod io;
+mod location;
 
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
 use bytes::Bytes;
+use checksum::{checksum, hex_to_base64, Algorithm};
+use du::Usage;
 use futures::future::join_all;
+use futures::Stream;
+use glob::{glob, Listing, Pattern};
+use location::{arn_region, S3Location};
+use std::pin::Pin;
 #[macro_use]
 extern crate lazy_static;
 use google_auth::TokenManager;
+use md5::{Digest, Md5};
 use pyo3::buffer::PyBuffer;
-use pyo3::exceptions::PyRuntimeError;
+use pyo3::create_exception;
+use pyo3::exceptions::{
+    PyFileExistsError, PyFileNotFoundError, PyIOError, PyPermissionError,
+    PyRuntimeError, PyTypeError, PyValueError,
+};
 use pyo3::prelude::*;
-use pyo3::types::{PyBytes, PyDict, PyTuple};
+use pyo3::types::{PyBytes, PyDict, PyIterator, PyTuple};
 use reqwest;
-use std::collections::HashMap;
+use rsa::pkcs1v15::SigningKey;
+use rsa::pkcs8::DecodePrivateKey;
+use rsa::signature::{SignatureEncoding, Signer};
+use rsa::RsaPrivateKey;
+use sha2::Sha256;
+use std::collections::{HashMap, HashSet};
 use std::slice;
 use std::str::FromStr;
-use std::sync::Mutex;
-use tokio::io::AsyncWriteExt;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::runtime::{Builder, Runtime};
+use tokio::sync::OnceCell;
 use urlencoding::encode;
 
+create_exception!(
+    rfsspec,
+    FileChangedError,
+    PyIOError,
+    "The file changed since it was opened, so a read could not continue."
+);
+create_exception!(
+    rfsspec,
+    PreconditionFailedError,
+    PyIOError,
+    "A conditional write found the target not in the state required."
+);
+create_exception!(
+    rfsspec,
+    ChecksumError,
+    PyIOError,
+    "Data did not match its checksum, so was corrupted on the way."
+);
+create_exception!(
+    rfsspec,
+    ObjectArchivedError,
+    PyIOError,
+    "The object is in an archive storage class, so must be restored to read."
+);
+
 lazy_static! {
     static ref RUNTIME: Runtime = Builder::new_current_thread()
         .max_blocking_threads(1)
@@ -27,6 +76,8 @@ lazy_static! {
     static ref CLIENT: reqwest::Client = reqwest::Client::new();
     static ref S3_CACHE: Mutex<HashMap<String, Client>> =
         Mutex::new(HashMap::new());
+    static ref S3_REGIONS: Mutex<HashMap<String, Arc<OnceCell<Option<String>>>>> =
+        Mutex::new(HashMap::new());
     static ref GCS_TOKEN: Mutex<HashMap<String, TokenManager>> =
         Mutex::new(HashMap::new());
 }
@@ -117,6 +168,77 @@ async fn get_url_or(
     Bytes::from(format!("HTTP ERROR: {}", out.err().unwrap().to_string()))
 }
 
+/// size and ETag of a URL, from the headers of a HEAD request, or of a GET
+/// if HEAD is not allowed
+async fn head_url(
+    url: &str, head: &HashMap<&str, String>,
+) -> PyResult<HashMap<String, String>> {
+    let send = |method| {
+        let mut req = CLIENT.request(method, url);
+        for (key, value) in head.iter() {
+            req = req.header(*key, value);
+        }
+        req.send()
+    };
+    let mut resp = send(reqwest::Method::HEAD).await;
+    if resp.as_ref().is_ok_and(|r| r.status().as_u16() == 405) {
+        // the body, of a GET, is not read
+        resp = send(reqwest::Method::GET).await;
+    }
+    let resp = resp.map_err(|e| {
+        PyIOError::new_err(format!("HTTP ERROR: {}: {}", url, e))
+    })?;
+    match resp.status().as_u16() {
+        404 => return Err(PyFileNotFoundError::new_err(url.to_string())),
+        s if s >= 400 => {
+            return Err(PyRuntimeError::new_err(format!(
+                "HTTP ERROR: {}: {}",
+                url,
+                resp.status()
+            )))
+        }
+        _ => (),
+    }
+    let mut output: HashMap<String, String> = HashMap::new();
+    output.insert("name".to_string(), url.to_string());
+    output.insert("type".to_string(), "file".to_string());
+    for (k, header) in [("size", "content-length"), ("ETag", "etag")] {
+        if let Some(v) = resp.headers().get(header) {
+            output.insert(k.to_string(), v.to_str().unwrap_or("").to_string());
+        }
+    }
+    Ok(output)
+}
+
+/// info_many(urls, concurrency=32, headers=None)
+/// --
+///
+/// Info of each URL, with up to ``concurrency`` requests at once, or None
+/// where not found
+///
+/// urls: list[str]
+/// concurrency: int
+/// headers: dict[str, str]
+#[pyfunction]
+#[pyo3(signature = (urls, concurrency=32, headers=None))]
+fn info_many(
+    py: Python, urls: Vec<&str>, concurrency: usize,
+    headers: Option<HashMap<&str, String>>,
+) -> PyResult<Vec<Option<HashMap<String, String>>>> {
+    let headers: HashMap<&str, String> = headers.unwrap_or_default();
+    let urls: Vec<String> = urls.into_iter().map(String::from).collect();
+    let coroutine = async {
+        let headers = &headers;
+        futures::stream::iter(urls)
+            .map(|u| async move { head_url(&u, headers).await })
+            .buffered(concurrency.max(1))
+            .collect::<Vec<PyResult<_>>>()
+            .await
+    };
+    let results = py.allow_threads(|| RUNTIME.block_on(coroutine));
+    results.into_iter().map(|r| missing_as_none(py, r)).collect()
+}
+
 /// cat_ranges(urls, starts=None, ends=None, headers=None, method=None)
 /// --
 ///
@@ -169,46 +291,342 @@ fn cat_ranges<'a>(
     PyTuple::new(py, result.iter().map(|r| PyBytes::new(py, &r[..])))
 }
 
+use aws_config::default_provider::credentials::DefaultCredentialsChain;
 use aws_config::profile::ProfileFileCredentialsProvider;
-use aws_sdk_s3::types::RequestPayer;
+use aws_config::provider_config::ProviderConfig;
+use aws_config::retry::RetryConfig;
+use aws_config::sts::AssumeRoleProvider;
+use aws_config::timeout::TimeoutConfig;
+use aws_config::web_identity_token::{
+    StaticConfiguration, WebIdentityTokenCredentialsProvider,
+};
+use aws_credential_types::provider::SharedCredentialsProvider;
+use aws_credential_types::Credentials;
+use aws_sdk_s3::error::{DisplayErrorContext, ProvideErrorMetadata};
+use aws_sdk_s3::operation::get_object::GetObjectError;
+use aws_sdk_s3::presigning::PresigningConfig;
+use aws_sdk_s3::primitives::DateTime;
+use aws_sdk_s3::types::{
+    BucketCannedAcl, BucketLocationConstraint, ChecksumAlgorithm,
+    ChecksumMode, CreateBucketConfiguration, GlacierJobParameters, Object,
+    ObjectCannedAcl, ObjectLockLegalHold, ObjectLockLegalHoldStatus,
+    ObjectLockMode, ObjectLockRetention, ObjectLockRetentionMode,
+    RequestPayer, RestoreRequest, ServerSideEncryption, StorageClass, Tag,
+    Tagging, Tier,
+};
 use aws_sdk_s3::Client;
+use aws_sigv4::http_request::SignableBody;
 use aws_smithy_http::body::SdkBody;
 use aws_smithy_http::result::SdkError;
+use aws_smithy_types::date_time::Format as DateTimeFormat;
 use aws_types::region::Region;
+use std::time::Duration;
 
-async fn s3(
-    region: Option<&str>, profile: Option<&str>, endpoint_url: Option<&str>,
-) -> Client {
-    let cname: String = vec![
-        region.unwrap_or("None"),
-        profile.unwrap_or("None"),
-        endpoint_url.unwrap_or("None"),
-    ]
-    .join("-");
+/// Options for constructing an S3 client, given as keyword arguments to the
+/// s3_* functions; the client is cached on all of them
+#[derive(Clone, Debug, Default)]
+struct S3Config {
+    region: Option<String>,
+    profile: Option<String>,
+    endpoint_url: Option<String>,
+    key: Option<String>,
+    secret: Option<String>,
+    token: Option<String>,
+    role_arn: Option<String>,
+    external_id: Option<String>,
+    role_session_name: Option<String>,
+    web_identity_token_file: Option<String>,
+    force_path_style: bool,
+    list_version: Option<u8>,
+    connect_timeout: Option<f64>,
+    read_timeout: Option<f64>,
+    operation_timeout: Option<f64>,
+    max_attempts: Option<u32>,
+    retry_backoff: Option<f64>,
+    disable_payload_signing: bool,
+}
+
+impl S3Config {
+    fn from_kwargs(kwargs: Option<&PyDict>) -> PyResult<S3Config> {
+        let mut config = S3Config::default();
+        for (k, v) in kwargs.iter().flat_map(|kw| kw.iter()) {
+            match k.extract::<&str>()? {
+                "region" => config.region = v.extract()?,
+                "profile" => config.profile = v.extract()?,
+                "endpoint_url" => config.endpoint_url = v.extract()?,
+                "key" => config.key = v.extract()?,
+                "secret" => config.secret = v.extract()?,
+                "token" => config.token = v.extract()?,
+                "role_arn" => config.role_arn = v.extract()?,
+                "external_id" => config.external_id = v.extract()?,
+                "role_session_name" => {
+                    config.role_session_name = v.extract()?
+                }
+                "web_identity_token_file" => {
+                    config.web_identity_token_file = v.extract()?
+                }
+                "force_path_style" => config.force_path_style = v.extract()?,
+                "list_version" => config.list_version = v.extract()?,
+                "connect_timeout" => config.connect_timeout = v.extract()?,
+                "read_timeout" => config.read_timeout = v.extract()?,
+                "operation_timeout" => {
+                    config.operation_timeout = v.extract()?
+                }
+                "max_attempts" => config.max_attempts = v.extract()?,
+                "retry_backoff" => config.retry_backoff = v.extract()?,
+                "disable_payload_signing" => {
+                    config.disable_payload_signing = v.extract()?
+                }
+                other => {
+                    return Err(PyTypeError::new_err(format!(
+                        "unexpected S3 option: {}",
+                        other
+                    )))
+                }
+            }
+        }
+        if config.key.is_some() != config.secret.is_some() {
+            return Err(PyValueError::new_err(
+                "key and secret must be given together",
+            ));
+        }
+        Ok(config)
+    }
+
+    /// Key for S3_CACHE: every option, but the credentials only as a hash,
+    /// so that secrets are never held or shown in the key
+    fn cache_key(&self) -> String {
+        let mut hasher = Sha256::new();
+        for part in [&self.key, &self.secret, &self.token] {
+            match part {
+                Some(p) => hasher.update([&[1], p.as_bytes()].concat()),
+                None => hasher.update([0]),
+            }
+        }
+        let options =
+            S3Config { key: None, secret: None, token: None, ..self.clone() };
+        format!("{:?} {:x}", options, hasher.finalize())
+    }
+
+    /// Where the credentials come from, before any role is assumed
+    async fn base_credentials(&self) -> SharedCredentialsProvider {
+        match (&self.key, &self.secret, &self.profile) {
+            (Some(key), Some(secret), _) => {
+                SharedCredentialsProvider::new(Credentials::new(
+                    key,
+                    secret,
+                    self.token.clone(),
+                    None,
+                    "rfsspec",
+                ))
+            }
+            (_, _, Some(pro)) => SharedCredentialsProvider::new(
+                ProfileFileCredentialsProvider::builder()
+                    .profile_name(pro)
+                    .build(),
+            ),
+            _ => SharedCredentialsProvider::new(
+                DefaultCredentialsChain::builder().build().await,
+            ),
+        }
+    }
+
+    /// Credentials provider for the client. Assumed-role and web-identity
+    /// credentials are temporary, and are refreshed by the SDK as they expire
+    async fn credentials(&self) -> Option<SharedCredentialsProvider> {
+        let provider_config = match &self.region {
+            Some(reg) => ProviderConfig::without_region()
+                .with_region(Some(Region::new(reg.clone()))),
+            None => ProviderConfig::with_default_region().await,
+        };
+        let session_name = self
+            .role_session_name
+            .clone()
+            .unwrap_or_else(|| "rfsspec".to_string());
+        match (&self.role_arn, &self.web_identity_token_file) {
+            (Some(role), Some(token_file)) => {
+                Some(SharedCredentialsProvider::new(
+                    WebIdentityTokenCredentialsProvider::builder()
+                        .configure(&provider_config)
+                        .static_configuration(StaticConfiguration {
+                            web_identity_token_file: token_file.into(),
+                            role_arn: role.clone(),
+                            session_name,
+                        })
+                        .build(),
+                ))
+            }
+            (Some(role), None) => {
+                let mut builder = AssumeRoleProvider::builder(role)
+                    .configure(&provider_config)
+                    .session_name(session_name)
+                    .region(Region::new(
+                        self.region.clone().unwrap_or("us-east-1".into()),
+                    ));
+                if let Some(ext) = &self.external_id {
+                    builder = builder.external_id(ext)
+                };
+                Some(SharedCredentialsProvider::new(
+                    builder.build(self.base_credentials().await),
+                ))
+            }
+            _ if self.key.is_some() || self.profile.is_some() => {
+                Some(self.base_credentials().await)
+            }
+            // SDK default chain, including web identity from env variables
+            _ => None,
+        }
+    }
+}
+
+async fn s3(config: &S3Config) -> Client {
+    let cname = config.cache_key();
     if S3_CACHE.lock().unwrap().contains_key(cname.as_str()) {
         // clone is free since "client" is actually an Arc pointing to real object
         return S3_CACHE.lock().unwrap().get(cname.as_str()).unwrap().clone();
     }
-    let mut shared_config = match profile {
-        None => aws_config::from_env(),
-        Some(pro) => aws_config::from_env().credentials_provider(
-            ProfileFileCredentialsProvider::builder()
-                .profile_name(pro)
-                .build(),
-        ),
+    let mut shared_config = aws_config::from_env();
+    if let Some(creds) = config.credentials().await {
+        shared_config = shared_config.credentials_provider(creds)
     };
-    if let Some(reg) = region {
-        shared_config = shared_config.region(Region::new(String::from(reg)))
+    if let Some(reg) = &config.region {
+        shared_config = shared_config.region(Region::new(reg.clone()))
     };
-    if let Some(end) = endpoint_url {
+    if let Some(end) = &config.endpoint_url {
         shared_config = shared_config.endpoint_url(end)
     };
+    if config.connect_timeout.is_some()
+        || config.read_timeout.is_some()
+        || config.operation_timeout.is_some()
+    {
+        let mut timeouts = TimeoutConfig::builder();
+        timeouts
+            .set_connect_timeout(config.connect_timeout.map(secs))
+            .set_read_timeout(config.read_timeout.map(secs))
+            .set_operation_timeout(config.operation_timeout.map(secs));
+        shared_config = shared_config.timeout_config(timeouts.build())
+    };
+    if config.max_attempts.is_some() || config.retry_backoff.is_some() {
+        let mut retry = RetryConfig::standard();
+        if let Some(n) = config.max_attempts {
+            retry = retry.with_max_attempts(n)
+        };
+        if let Some(b) = config.retry_backoff {
+            retry = retry.with_initial_backoff(secs(b))
+        };
+        shared_config = shared_config.retry_config(retry)
+    };
     let shared_config = shared_config.load().await;
-    let client = Client::new(&shared_config);
+    let client = Client::from_conf(
+        aws_sdk_s3::config::Builder::from(&shared_config)
+            .force_path_style(config.force_path_style)
+            .build(),
+    );
     S3_CACHE.lock().unwrap().insert(cname, client.clone());
     client
 }
 
+/// Send a fluent S3 request, first removing request signing if `anon`, or
+/// only payload signing if `unsigned_payload`
+macro_rules! send {
+    (@map $req:expr, $map:expr) => {
+        match $req.customize().await {
+            Ok(op) => match op.map_operation($map) {
+                Ok(op) => op.send().await,
+                Err(never) => match never {},
+            },
+            Err(e) => Err(e),
+        }
+    };
+    ($req:expr, $anon:expr) => {
+        send!($req, $anon, false)
+    };
+    ($req:expr, $anon:expr, $unsigned_payload:expr) => {
+        if $anon {
+            send!(@map $req, make_unsigned)
+        } else if $unsigned_payload {
+            send!(@map $req, make_unsigned_payload)
+        } else {
+            $req.send().await
+        }
+    };
+}
+
+/// Region of a bucket, from the "x-amz-bucket-region" header S3 sends with
+/// the PermanentRedirect (or other error) for a request to the wrong region
+async fn s3_bucket_region(
+    s3: &Client, bucket: &str, anon: bool,
+) -> Option<String> {
+    let req = s3.head_bucket().bucket(bucket);
+    match send!(req, anon) {
+        Ok(_) => s3.conf().region().map(|r| r.to_string()),
+        Err(e) => e
+            .raw_response()?
+            .http()
+            .headers()
+            .get("x-amz-bucket-region")?
+            .to_str()
+            .ok()
+            .map(|r| r.to_string()),
+    }
+}
+
+/// Region of a bucket from S3_REGIONS, looked up if not known. Concurrent
+/// callers share one lookup; a failed lookup is not kept, to be tried again.
+async fn s3_cached_region(
+    client: &Client, bucket: &str, anon: bool,
+) -> Option<String> {
+    let cell = S3_REGIONS
+        .lock()
+        .unwrap()
+        .entry(bucket.to_string())
+        .or_default()
+        .clone();
+    let region = cell
+        .get_or_init(|| s3_bucket_region(client, bucket, anon))
+        .await
+        .clone();
+    if region.is_none() {
+        let mut regions = S3_REGIONS.lock().unwrap();
+        if regions.get(bucket).is_some_and(|c| Arc::ptr_eq(c, &cell)) {
+            regions.remove(bucket);
+        }
+    }
+    region
+}
+
+/// Drop the cached region of a bucket, as when S3 redirects a request
+/// because the bucket is now elsewhere
+fn s3_forget_region(bucket: &str) {
+    S3_REGIONS.lock().unwrap().remove(bucket);
+}
+
+/// Client for requests to the given bucket: the bucket's region is looked up
+/// once and cached, and a client for that region taken from S3_CACHE. Custom
+/// endpoints are used as given. An access point ARN names its own region.
+async fn s3_bucket(config: &S3Config, bucket: &str, anon: bool) -> Client {
+    let client = s3(config).await;
+    if config.endpoint_url.is_some() || bucket.is_empty() {
+        return client;
+    }
+    let region = match arn_region(bucket) {
+        Some(r) => r.to_string(),
+        None => match s3_cached_region(&client, bucket, anon).await {
+            Some(r) => r,
+            // no such bucket, or no answer: let the request itself fail
+            None => return client,
+        },
+    };
+    if config.region.as_deref() == Some(region.as_str()) {
+        return client;
+    }
+    s3(&S3Config { region: Some(region), ..config.clone() }).await
+}
+
+fn secs(s: f64) -> Duration {
+    Duration::from_secs_f64(s)
+}
+
 fn make_unsigned<O, Retry>(
     mut operation: aws_smithy_http::operation::Operation<O, Retry>,
 ) -> Result<
@@ -217,7 +635,7 @@ fn make_unsigned<O, Retry>(
 > {
     {
         let mut props = operation.properties_mut();
-        let mut signing_config = props
+        let signing_config = props
             .get_mut::<aws_sig_auth::signer::OperationSigningConfig>()
             .expect("has signing_config");
         signing_config.signing_requirements =
@@ -226,93 +644,622 @@ fn make_unsigned<O, Retry>(
     Ok(operation)
 }
 
+/// Sign the request, but not the body, which then needn't be hashed
+fn make_unsigned_payload<O, Retry>(
+    mut operation: aws_smithy_http::operation::Operation<O, Retry>,
+) -> Result<
+    aws_smithy_http::operation::Operation<O, Retry>,
+    std::convert::Infallible,
+> {
+    operation
+        .properties_mut()
+        .insert(SignableBody::<'static>::UnsignedPayload);
+    Ok(operation)
+}
+
+/// Preconditions for a write: if_none_match="*" to only create a new object,
+/// if_match to only replace the object with the given ETag
+#[derive(Clone, Default)]
+struct WriteCondition {
+    if_none_match: Option<String>,
+    if_match: Option<String>,
+}
+
+impl WriteCondition {
+    fn new(
+        if_none_match: Option<&str>, if_match: Option<&str>,
+    ) -> PyResult<WriteCondition> {
+        if if_none_match.is_some_and(|v| v != "*") {
+            return Err(PyValueError::new_err(
+                "if_none_match may only be \"*\"",
+            ));
+        }
+        if let Some(etag) = if_match {
+            if reqwest::header::HeaderValue::from_str(etag).is_err() {
+                return Err(PyValueError::new_err(format!(
+                    "if_match is not a valid header value: {:?}",
+                    etag
+                )));
+            }
+        }
+        Ok(WriteCondition {
+            if_none_match: if_none_match.map(|v| v.to_string()),
+            if_match: if_match.map(|v| v.to_string()),
+        })
+    }
+
+    fn is_none(&self) -> bool {
+        self.if_none_match.is_none() && self.if_match.is_none()
+    }
+}
+
+/// Add the write's preconditions as headers, since the SDK's builders lack
+/// them for writes, then remove signing as for send!
+fn with_conditions<O, Retry>(
+    mut operation: aws_smithy_http::operation::Operation<O, Retry>,
+    cond: &WriteCondition, anon: bool, unsigned_payload: bool,
+) -> Result<
+    aws_smithy_http::operation::Operation<O, Retry>,
+    std::convert::Infallible,
+> {
+    let headers = operation.request_mut().headers_mut();
+    // both were checked to be valid header values by WriteCondition::new
+    if let Some(etag) = &cond.if_match {
+        headers.insert("If-Match", etag.parse().expect("valid If-Match"));
+    }
+    if let Some(any) = &cond.if_none_match {
+        headers.insert(
+            "If-None-Match",
+            any.parse().expect("valid If-None-Match"),
+        );
+    }
+    if anon {
+        return make_unsigned(operation);
+    }
+    if unsigned_payload {
+        return make_unsigned_payload(operation);
+    }
+    Ok(operation)
+}
+
+/// Send a fluent S3 write request, subject to the WriteCondition
+macro_rules! send_if {
+    ($req:expr, $cond:expr, $anon:expr, $unsigned_payload:expr) => {
+        if $cond.is_none() {
+            send!($req, $anon, $unsigned_payload)
+        } else {
+            let (cond, anon, unsigned) = ($cond, $anon, $unsigned_payload);
+            send!(@map $req, |op| with_conditions(op, cond, anon, unsigned))
+        }
+    };
+}
+
+/// a result for one of many paths, with FileNotFoundError as None
+fn missing_as_none<T>(py: Python, result: PyResult<T>) -> PyResult<Option<T>> {
+    match result {
+        Ok(v) => Ok(Some(v)),
+        Err(e) if e.is_instance_of::<PyFileNotFoundError>(py) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn precondition_err(path: &str) -> PyErr {
+    PreconditionFailedError::new_err(format!(
+        "{}: the object's state did not meet the write's condition",
+        path
+    ))
+}
+
+/// HTTP status of a failed S3 call, if the service responded at all
+fn s3_status<E>(err: &SdkError<E>) -> Option<u16> {
+    err.raw_response().map(|r| r.http().status().as_u16())
+}
+
+/// The modeled error of a failed S3 call, if the service sent one
+fn s3_service_err<E>(err: &SdkError<E>) -> Option<&E> {
+    match err {
+        SdkError::ServiceError(e) => Some(e.err()),
+        _ => None,
+    }
+}
+
+/// Bucket and key of an S3 path, URL or access point ARN; ValueError if it
+/// is malformed
+fn s3_path(path: &str) -> PyResult<S3Location> {
+    S3Location::parse(path).map_err(|e| {
+        PyValueError::new_err(format!("S3 ERROR: {}: {}", path, e))
+    })
+}
+
+/// As s3_path, for an object, which must have a key
+fn s3_key_path(path: &str) -> PyResult<S3Location> {
+    let loc = s3_path(path)?;
+    if loc.key.is_empty() {
+        return Err(PyValueError::new_err(format!(
+            "S3 ERROR: {}: no key",
+            path
+        )));
+    }
+    Ok(loc)
+}
+
+/// Convert an S3 SDK error into the closest python exception
+fn s3_err<E>(path: &str, err: SdkError<E>) -> PyErr
+where
+    E: std::error::Error + 'static,
+{
+    match s3_status(&err) {
+        Some(404) => PyFileNotFoundError::new_err(path.to_string()),
+        Some(403) => PyPermissionError::new_err(format!(
+            "{}: {}",
+            path,
+            DisplayErrorContext(&err)
+        )),
+        Some(301) => {
+            // PermanentRedirect: the cached region is stale, so the next
+            // request looks it up again
+            if let Ok(loc) = S3Location::parse(path) {
+                s3_forget_region(&loc.bucket);
+            }
+            PyRuntimeError::new_err(format!(
+                "S3 ERROR: {}: bucket is in another region: {}",
+                path,
+                DisplayErrorContext(&err)
+            ))
+        }
+        _ => PyRuntimeError::new_err(format!(
+            "S3 ERROR: {}: {}",
+            path,
+            DisplayErrorContext(&err)
+        )),
+    }
+}
+
+/// for a read of an object in an archive storage class, which must be
+/// restored first
+fn archived_err(path: &str, err: &GetObjectError) -> Option<PyErr> {
+    match err {
+        GetObjectError::InvalidObjectState(e) => {
+            Some(ObjectArchivedError::new_err(format!(
+                "{} is archived ({}); restore it before reading",
+                path,
+                e.storage_class().map_or("", |c| c.as_str())
+            )))
+        }
+        _ => None,
+    }
+}
+
+/// Server-side encryption settings for requests that accept them
+#[derive(Clone, Default)]
+struct S3Encryption {
+    sse: Option<ServerSideEncryption>,
+    kms_key_id: Option<String>,
+    customer_key: Option<String>,
+    customer_key_md5: Option<String>,
+}
+
+impl S3Encryption {
+    /// sse: "AES256" or "aws:kms"; customer_key: base64 256-bit key (SSE-C)
+    fn new(
+        sse: Option<&str>, kms_key_id: Option<&str>,
+        customer_key: Option<&str>,
+    ) -> PyResult<S3Encryption> {
+        let customer_key_md5 = match customer_key {
+            None => None,
+            Some(k) => {
+                let raw = STANDARD.decode(k).map_err(|e| {
+                    PyValueError::new_err(format!(
+                        "sse_customer_key must be base64: {}",
+                        e
+                    ))
+                })?;
+                if raw.len() != 32 {
+                    return Err(PyValueError::new_err(format!(
+                        "sse_customer_key must be 32 bytes, not {}",
+                        raw.len()
+                    )));
+                }
+                Some(STANDARD.encode(Md5::digest(raw)))
+            }
+        };
+        Ok(S3Encryption {
+            sse: sse.map(ServerSideEncryption::from),
+            kms_key_id: kms_key_id.map(|k| k.to_string()),
+            customer_key: customer_key.map(|k| k.to_string()),
+            customer_key_md5,
+        })
+    }
+
+    fn customer_algorithm(&self) -> Option<String> {
+        self.customer_key.as_ref().map(|_| "AES256".to_string())
+    }
+}
+
+/// Apply encryption to a fluent request; with `customer`, only the SSE-C
+/// headers, as accepted by reads and part uploads
+macro_rules! encrypt {
+    ($req:expr, $enc:expr) => {
+        encrypt!($req, $enc, customer)
+            .set_server_side_encryption($enc.sse.clone())
+            .set_ssekms_key_id($enc.kms_key_id.clone())
+    };
+    ($req:expr, $enc:expr, customer) => {
+        $req.set_sse_customer_algorithm($enc.customer_algorithm())
+            .set_sse_customer_key($enc.customer_key.clone())
+            .set_sse_customer_key_md5($enc.customer_key_md5.clone())
+    };
+}
+
+/// Headers, user metadata and placement for new objects
+#[derive(Clone, Default)]
+struct S3WriteOptions {
+    content_type: Option<String>,
+    cache_control: Option<String>,
+    content_encoding: Option<String>,
+    metadata: Option<HashMap<String, String>>,
+    acl: Option<ObjectCannedAcl>,
+    storage_class: Option<StorageClass>,
+    lock_mode: Option<ObjectLockMode>,
+    retain_until: Option<DateTime>,
+    legal_hold: Option<ObjectLockLegalHoldStatus>,
+}
+
+impl S3WriteOptions {
+    /// acl: canned ACL, e.g., "public-read"; storage_class: e.g., "STANDARD_IA"
+    fn new(
+        content_type: Option<String>, cache_control: Option<String>,
+        content_encoding: Option<String>,
+        metadata: Option<HashMap<String, String>>, acl: Option<&str>,
+        storage_class: Option<&str>,
+    ) -> S3WriteOptions {
+        S3WriteOptions {
+            content_type,
+            cache_control,
+            content_encoding,
+            metadata,
+            acl: acl.map(ObjectCannedAcl::from),
+            storage_class: storage_class.map(StorageClass::from),
+            ..Default::default()
+        }
+    }
+
+    /// Object Lock settings: mode, "GOVERNANCE" or "COMPLIANCE", with the
+    /// epoch seconds until which to retain, and whether on legal hold
+    fn lock(
+        self, mode: Option<&str>, retain_until: Option<f64>,
+        legal_hold: Option<bool>,
+    ) -> PyResult<S3WriteOptions> {
+        let lock_mode = match mode.map(|m| m.to_uppercase()).as_deref() {
+            None => None,
+            Some("GOVERNANCE") => Some(ObjectLockMode::Governance),
+            Some("COMPLIANCE") => Some(ObjectLockMode::Compliance),
+            Some(m) => {
+                return Err(PyValueError::new_err(format!(
+                    "unknown object lock mode {}",
+                    m
+                )))
+            }
+        };
+        if lock_mode.is_some() != retain_until.is_some() {
+            return Err(PyValueError::new_err(
+                "object lock mode and retain-until date go together",
+            ));
+        }
+        Ok(S3WriteOptions {
+            lock_mode,
+            retain_until: retain_until.map(DateTime::from_secs_f64),
+            legal_hold: legal_hold.map(|on| match on {
+                true => ObjectLockLegalHoldStatus::On,
+                false => ObjectLockLegalHoldStatus::Off,
+            }),
+            ..self
+        })
+    }
+
+    /// Whether Object Lock settings are given, for which S3 requires a
+    /// checksum of the data
+    fn locks(&self) -> bool {
+        self.lock_mode.is_some() || self.legal_hold.is_some()
+    }
+
+    /// The content type for a key: as given, else guessed from its extension
+    fn content_type(&self, key: &str) -> Option<String> {
+        self.content_type.clone().or_else(|| {
+            mime_guess::from_path(key).first_raw().map(|m| m.to_string())
+        })
+    }
+}
+
+/// Apply write options to a fluent request creating the object at key
+macro_rules! describe {
+    ($req:expr, $opts:expr, $key:expr) => {
+        $req.set_content_type($opts.content_type($key))
+            .set_cache_control($opts.cache_control.clone())
+            .set_content_encoding($opts.content_encoding.clone())
+            .set_metadata($opts.metadata.clone())
+            .set_acl($opts.acl.clone())
+            .set_storage_class($opts.storage_class.clone())
+            .set_object_lock_mode($opts.lock_mode.clone())
+            .set_object_lock_retain_until_date($opts.retain_until)
+            .set_object_lock_legal_hold_status($opts.legal_hold.clone())
+    };
+}
+
+fn checksum_algorithm(name: Option<&str>) -> PyResult<Option<Algorithm>> {
+    name.map(|n| {
+        Algorithm::from_name(n).ok_or_else(|| {
+            PyValueError::new_err(format!("unknown checksum algorithm {}", n))
+        })
+    })
+    .transpose()
+}
+
+/// S3's name for an additional checksum; MD5 is sent as Content-MD5 instead
+fn s3_checksum_algorithm(
+    algorithm: Option<Algorithm>,
+) -> Option<ChecksumAlgorithm> {
+    match algorithm? {
+        Algorithm::Crc32c => Some(ChecksumAlgorithm::Crc32C),
+        Algorithm::Sha256 => Some(ChecksumAlgorithm::Sha256),
+        Algorithm::Md5 => None,
+    }
+}
+
+/// Send the (algorithm, base64 checksum) of a request's body, for the server
+/// to verify
+macro_rules! checksum {
+    ($req:expr, $sum:expr) => {{
+        let sum: &Option<(Algorithm, String)> = $sum;
+        let value = |a: Algorithm| {
+            sum.as_ref().filter(|(s, _)| *s == a).map(|(_, v)| v.clone())
+        };
+        $req.set_checksum_crc32_c(value(Algorithm::Crc32c))
+            .set_checksum_sha256(value(Algorithm::Sha256))
+            .set_content_md5(value(Algorithm::Md5))
+    }};
+}
+
+/// Whether encryption means that an object's ETag is not the MD5 of its data
+fn s3_encrypted(
+    sse: Option<&ServerSideEncryption>, customer_algorithm: Option<&str>,
+) -> bool {
+    customer_algorithm.is_some()
+        || sse.is_some_and(|s| s.as_str().starts_with("aws:kms"))
+}
+
+/// The checksum S3 recorded for all of an object's (or part's) data, if any:
+/// an additional checksum, else the MD5 in an unencrypted single-part ETag
+fn s3_stored_checksum(
+    crc32c: Option<&str>, sha256: Option<&str>, etag: Option<&str>,
+    encrypted: bool,
+) -> Option<(Algorithm, String)> {
+    // those of multipart objects are "<checksum of part checksums>-<parts>"
+    let whole = |c: Option<&str>| {
+        c.filter(|c| !c.contains('-')).map(|c| c.to_string())
+    };
+    let md5 = || match encrypted {
+        true => None,
+        false => etag.and_then(|e| hex_to_base64(e.trim_matches('"'))),
+    };
+    whole(crc32c)
+        .map(|c| (Algorithm::Crc32c, c))
+        .or_else(|| whole(sha256).map(|c| (Algorithm::Sha256, c)))
+        .or_else(|| md5().map(|c| (Algorithm::Md5, c)))
+}
+
+fn checksum_err(
+    path: &str, algorithm: Algorithm, expected: &str, actual: &str,
+) -> PyErr {
+    ChecksumError::new_err(format!(
+        "{}: {} checksum {} does not match expected {}",
+        path,
+        algorithm.name(),
+        actual,
+        expected
+    ))
+}
+
+/// Check that S3 recorded the checksum sent with an upload, where it says
+fn s3_check_upload(
+    url: &str, sum: &Option<(Algorithm, String)>,
+    stored: Option<(Algorithm, String)>,
+) -> PyResult<()> {
+    match (sum, stored) {
+        (Some((a, expected)), Some((b, actual)))
+            if *a == b && *expected != actual =>
+        {
+            Err(checksum_err(url, b, expected, &actual))
+        }
+        _ => Ok(()),
+    }
+}
+
 #[pyfunction]
+#[pyo3(signature = (url, sse=None, sse_kms_key_id=None, sse_customer_key=None, content_type=None, cache_control=None, content_encoding=None, metadata=None, acl=None, storage_class=None, object_lock_mode=None, object_lock_retain_until=None, object_lock_legal_hold=None, requester_pays=false, anon=false, checksum_algorithm=None, **config))]
+#[allow(clippy::too_many_arguments)]
 fn s3_init_upload(
-    py: Python, url: &str, region: Option<&str>, profile: Option<&str>,
-    endpoint_url: Option<&str>,
+    py: Python, url: &str, sse: Option<&str>, sse_kms_key_id: Option<&str>,
+    sse_customer_key: Option<&str>, content_type: Option<String>,
+    cache_control: Option<String>, content_encoding: Option<String>,
+    metadata: Option<HashMap<String, String>>, acl: Option<&str>,
+    storage_class: Option<&str>, object_lock_mode: Option<&str>,
+    object_lock_retain_until: Option<f64>,
+    object_lock_legal_hold: Option<bool>, requester_pays: bool, anon: bool,
+    checksum_algorithm: Option<&str>, config: Option<&PyDict>,
 ) -> PyResult<String> {
-    let out = url.split_once("/");
-    let mut s = String::new();
-    match out {
-        None => s.extend("S3 ERROR: bad path".chars()),
-        Some((bucket, key)) => {
-            let coroutine = async {
-                let s3_client = s3(region, profile, endpoint_url).await;
-                let resp = s3_client
-                    .create_multipart_upload()
-                    .bucket(bucket)
-                    .key(key)
-                    .send()
-                    .await;
-                s.extend(resp.unwrap().upload_id().unwrap().chars());
-            };
-            py.allow_threads(|| RUNTIME.block_on(coroutine));
-        }
-    }
-    Ok(s)
+    let config = S3Config::from_kwargs(config)?;
+    let algorithm = self::checksum_algorithm(checksum_algorithm)?;
+    let loc = s3_key_path(url)?;
+    let (bucket, key) = loc.parts();
+    let enc = S3Encryption::new(sse, sse_kms_key_id, sse_customer_key)?;
+    let opts = S3WriteOptions::new(
+        content_type,
+        cache_control,
+        content_encoding,
+        metadata,
+        acl,
+        storage_class,
+    )
+    .lock(
+        object_lock_mode,
+        object_lock_retain_until,
+        object_lock_legal_hold,
+    )?;
+    let coroutine = async {
+        let s3_client = s3_bucket(&config, bucket, anon).await;
+        let req = s3_client
+            .create_multipart_upload()
+            .bucket(bucket)
+            .key(key)
+            .set_checksum_algorithm(s3_checksum_algorithm(algorithm))
+            .set_request_payer(
+                requester_pays.then_some(RequestPayer::Requester),
+            );
+        send!(describe!(encrypt!(req, enc), opts, key), anon)
+            .map_err(|e| s3_err(url, e))
+    };
+    let resp = py.allow_threads(|| RUNTIME.block_on(coroutine))?;
+    Ok(resp.upload_id().unwrap_or_default().to_string())
 }
 
 #[pyfunction]
+#[pyo3(signature = (url, mpu, data, part, sse_customer_key=None, requester_pays=false, anon=false, checksum_algorithm=None, **config))]
+#[allow(clippy::too_many_arguments)]
 fn s3_upload_chunk(
     py: Python, url: &str, mpu: &str, data: &PyAny, part: i32,
-    region: Option<&str>, profile: Option<&str>, endpoint_url: Option<&str>,
-) -> String {
-    let out = url.split_once("/");
+    sse_customer_key: Option<&str>, requester_pays: bool, anon: bool,
+    checksum_algorithm: Option<&str>, config: Option<&PyDict>,
+) -> PyResult<String> {
+    let config = S3Config::from_kwargs(config)?;
+    let loc = s3_key_path(url)?;
+    let (bucket, key) = loc.parts();
     let data: &[u8] = py_to_byteslice(data);
+    let enc = S3Encryption::new(None, None, sse_customer_key)?;
+    let sum = self::checksum_algorithm(checksum_algorithm)?
+        .map(|a| (a, checksum(a, data)));
     let coroutine = async {
-        let s3_client = s3(region, profile, endpoint_url).await;
-        let (bucket, key) = out.unwrap();
-        s3_client
+        let s3_client = s3_bucket(&config, bucket, anon).await;
+        let req = s3_client
             .upload_part()
             .bucket(bucket)
             .key(key)
             .upload_id(mpu)
             .part_number(part)
-            .body(Bytes::from_static(data).into())
-            .send()
-            .await
-            .unwrap()
+            .set_request_payer(
+                requester_pays.then_some(RequestPayer::Requester),
+            )
+            .body(Bytes::from_static(data).into());
+        send!(
+            checksum!(encrypt!(req, enc, customer), &sum),
+            anon,
+            config.disable_payload_signing
+        )
+        .map_err(|e| s3_err(url, e))
     };
-    let res = py.allow_threads(|| RUNTIME.block_on(coroutine));
-    res.e_tag().unwrap().to_string()
+    let res = py.allow_threads(|| RUNTIME.block_on(coroutine))?;
+    let encrypted = s3_encrypted(
+        res.server_side_encryption(),
+        res.sse_customer_algorithm(),
+    );
+    s3_check_upload(
+        url,
+        &sum,
+        s3_stored_checksum(
+            res.checksum_crc32_c(),
+            res.checksum_sha256(),
+            res.e_tag(),
+            encrypted,
+        ),
+    )?;
+    Ok(res.e_tag().unwrap_or_default().to_string())
 }
 
-use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
+use aws_sdk_s3::operation::list_multipart_uploads::ListMultipartUploadsError;
+use aws_sdk_s3::types::{
+    CompletedMultipartUpload, CompletedPart, MultipartUpload, Part,
+};
 
 /// parts: dict(part_number: etag)
+/// if_none_match, if_match: complete only if the key does not exist ("*"),
+/// or has this ETag; else raise PreconditionFailedError
+///
+/// Returns the VersionId of the new object, if the bucket is versioned
 #[pyfunction]
+#[pyo3(signature = (url, mpu, parts, if_none_match=None, if_match=None, requester_pays=false, anon=false, checksum_algorithm=None, **config))]
+#[allow(clippy::too_many_arguments)]
 fn s3_complete_upload(
     py: Python, url: &str, mpu: &str, mut parts: HashMap<i32, &str>,
-    region: Option<&str>, profile: Option<&str>, endpoint_url: Option<&str>,
-) -> PyResult<()> {
-    let out = url.split_once("/");
+    if_none_match: Option<&str>, if_match: Option<&str>, requester_pays: bool,
+    anon: bool, checksum_algorithm: Option<&str>, config: Option<&PyDict>,
+) -> PyResult<Option<String>> {
+    let config = S3Config::from_kwargs(config)?;
+    let cond = WriteCondition::new(if_none_match, if_match)?;
+    let algorithm = self::checksum_algorithm(checksum_algorithm)?;
+    let loc = s3_key_path(url)?;
+    let (bucket, key) = loc.parts();
     let coroutine = async {
-        let s3_client = s3(region, profile, endpoint_url).await;
-        let (bucket, key) = out.unwrap();
+        let s3_client = s3_bucket(&config, bucket, anon).await;
+        // with an additional checksum, each part's must be given, as recorded
+        let mut sums: HashMap<i32, Part> = HashMap::new();
+        if s3_checksum_algorithm(algorithm).is_some() {
+            let mut marker: Option<String> = None;
+            loop {
+                let req = s3_client
+                    .list_parts()
+                    .bucket(bucket)
+                    .key(key)
+                    .upload_id(mpu)
+                    .set_part_number_marker(marker)
+                    .set_request_payer(
+                        requester_pays.then_some(RequestPayer::Requester),
+                    );
+                let page = send!(req, anon).map_err(|e| s3_err(url, e))?;
+                for part in page.parts().unwrap_or_default() {
+                    sums.insert(part.part_number(), part.clone());
+                }
+                if !page.is_truncated() {
+                    break;
+                }
+                marker = page.next_part_number_marker().map(|t| t.to_string());
+            }
+        }
         let part_info: Vec<CompletedPart> = parts
             .drain()
             .map(|(part, etag)| {
-                CompletedPart::builder().e_tag(etag).part_number(part).build()
+                let sum = sums.get(&part);
+                CompletedPart::builder()
+                    .e_tag(etag)
+                    .part_number(part)
+                    .set_checksum_crc32_c(
+                        sum.and_then(|p| p.checksum_crc32_c()).map(Into::into),
+                    )
+                    .set_checksum_sha256(
+                        sum.and_then(|p| p.checksum_sha256()).map(Into::into),
+                    )
+                    .build()
             })
             .collect();
-        let x = s3_client
+        let req = s3_client
             .complete_multipart_upload()
             .bucket(bucket)
             .key(key)
             .upload_id(mpu)
+            .set_request_payer(
+                requester_pays.then_some(RequestPayer::Requester),
+            )
             .multipart_upload(
                 CompletedMultipartUpload::builder()
                     .set_parts(Some(part_info))
                     .build(),
-            )
-            .send()
-            .await;
-        x
+            );
+        Ok::<_, PyErr>(send_if!(req, &cond, anon, false))
     };
-    let res = py.allow_threads(|| RUNTIME.block_on(coroutine));
+    let res = py.allow_threads(|| RUNTIME.block_on(coroutine))?;
     match res {
-        Ok(_) => Ok(()),
-        Err(e) => Err(PyRuntimeError::new_err(e.to_string())),
+        Ok(r) => Ok(r.version_id().map(|v| v.to_string())),
+        Err(e) if s3_status(&e) == Some(412) => Err(precondition_err(url)),
+        Err(e) => Err(s3_err(url, e)),
     }
 }
 //            part_info = {"Parts": self.parts}
@@ -324,405 +1271,3128 @@ fn s3_complete_upload(
 //                 MultipartUpload=part_info,
 //             )
 
-use aws_smithy_http::byte_stream::ByteStream;
+/// abandon a multipart upload, discarding any parts already uploaded
+#[pyfunction]
+#[pyo3(signature = (url, mpu, requester_pays=false, anon=false, **config))]
+fn s3_abort_upload(
+    py: Python, url: &str, mpu: &str, requester_pays: bool, anon: bool,
+    config: Option<&PyDict>,
+) -> PyResult<()> {
+    let config = S3Config::from_kwargs(config)?;
+    let loc = s3_key_path(url)?;
+    let (bucket, key) = loc.parts();
+    let coroutine = async {
+        let s3_client = s3_bucket(&config, bucket, anon).await;
+        let req = s3_client
+            .abort_multipart_upload()
+            .bucket(bucket)
+            .key(key)
+            .upload_id(mpu)
+            .set_request_payer(
+                requester_pays.then_some(RequestPayer::Requester),
+            );
+        send!(req, anon).map_err(|e| s3_err(url, e))
+    };
+    py.allow_threads(|| RUNTIME.block_on(coroutine))?;
+    Ok(())
+}
 
-/// No-copy view of internal buffer of any python object supporting buffers
-#[inline(always)]
-fn py_to_byteslice(value: &PyAny) -> &'static mut [u8] {
-    let buf: PyBuffer<u8> = value.extract().unwrap();
-    unsafe {
-        slice::from_raw_parts_mut(buf.buf_ptr() as *mut u8, buf.len_bytes())
+/// every in-progress multipart upload in bucket with keys starting prefix
+async fn s3_uploads(
+    s3: &Client, bucket: &str, prefix: &str,
+) -> Result<Vec<MultipartUpload>, SdkError<ListMultipartUploadsError>> {
+    let mut output: Vec<MultipartUpload> = Vec::new();
+    let mut key_marker: Option<String> = None;
+    let mut upload_marker: Option<String> = None;
+    loop {
+        let page = s3
+            .list_multipart_uploads()
+            .bucket(bucket)
+            .prefix(prefix)
+            .set_key_marker(key_marker)
+            .set_upload_id_marker(upload_marker)
+            .send()
+            .await?;
+        output.extend(page.uploads().unwrap_or_default().iter().cloned());
+        if !page.is_truncated() {
+            break;
+        }
+        key_marker = page.next_key_marker().map(|t| t.to_string());
+        upload_marker = page.next_upload_id_marker().map(|t| t.to_string());
     }
+    Ok(output)
 }
 
-#[pyfunction]
-fn s3_pipe(
-    py: Python, data: &PyDict, region: Option<&str>, profile: Option<&str>,
-    endpoint_url: Option<&str>,
-) -> Vec<String> {
-    // no-copy convert bytes to u8 slices
-    let mut data_map: HashMap<&str, &[u8]> =
-        HashMap::with_capacity(data.len());
-    data.iter().for_each(|(key, value)| {
-        let url: &str = key.extract().unwrap();
-        data_map.insert(url, py_to_byteslice(value));
-    });
+fn upload_to_map(
+    bucket: &str, up: &MultipartUpload,
+) -> HashMap<String, String> {
+    let mut h: HashMap<String, String> = HashMap::new();
+    h.insert(
+        "name".to_string(),
+        format!("{}/{}", bucket, up.key().unwrap_or("")),
+    );
+    h.insert("UploadId".to_string(), up.upload_id().unwrap_or("").into());
+    h.insert("Initiated".to_string(), s3_time(up.initiated()));
+    h
+}
 
-    // perform uploads
+/// lists in-progress multipart uploads below "bucket/prefix"
+#[pyfunction]
+#[pyo3(signature = (path, **config))]
+fn s3_list_uploads<'py>(
+    py: Python<'py>, path: &str, config: Option<&PyDict>,
+) -> PyResult<&'py PyTuple> {
+    let config = S3Config::from_kwargs(config)?;
+    let loc = s3_path(path)?;
+    let (bucket, prefix) = loc.parts();
     let coroutine = async {
-        let s3_client = s3(region, profile, endpoint_url).await;
-        let mut results = join_all(data_map.drain().map(|(url, data)| {
-            let out = url.split_once("/");
-            let (bucket, key) = out.unwrap();
+        let s3_client = s3_bucket(&config, bucket, false).await;
+        s3_uploads(&s3_client, bucket, prefix)
+            .await
+            .map_err(|e| s3_err(path, e))
+    };
+    let uploads = py.allow_threads(|| RUNTIME.block_on(coroutine))?;
+    Ok(PyTuple::new(
+        py,
+        uploads.iter().map(|u| upload_to_map(bucket, u).to_object(py)),
+    ))
+}
+
+/// aborts in-progress multipart uploads below "bucket/prefix" which were
+/// started more than `age` seconds ago, returning those aborted
+#[pyfunction]
+#[pyo3(signature = (path, age, **config))]
+fn s3_abort_stale_uploads<'py>(
+    py: Python<'py>, path: &str, age: f64, config: Option<&PyDict>,
+) -> PyResult<&'py PyTuple> {
+    let config = S3Config::from_kwargs(config)?;
+    let loc = s3_path(path)?;
+    let (bucket, prefix) = loc.parts();
+    let now =
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs_f64();
+    let coroutine = async {
+        let s3_client = s3_bucket(&config, bucket, false).await;
+        let stale: Vec<MultipartUpload> =
+            s3_uploads(&s3_client, bucket, prefix)
+                .await
+                .map_err(|e| s3_err(path, e))?
+                .into_iter()
+                .filter(|u| match u.initiated() {
+                    Some(t) => (t.secs() as f64) <= now - age,
+                    None => false,
+                })
+                .collect();
+        join_all(stale.iter().map(|u| {
             s3_client
-                .put_object()
+                .abort_multipart_upload()
+                .bucket(bucket)
+                .set_key(u.key().map(|k| k.to_string()))
+                .set_upload_id(u.upload_id().map(|k| k.to_string()))
+                .send()
+        }))
+        .await
+        .into_iter()
+        .zip(stale.iter())
+        .map(|(r, u)| match r {
+            Ok(_) => Ok(upload_to_map(bucket, u)),
+            Err(e) => Err(s3_err(u.key().unwrap_or(path), e)),
+        })
+        .collect::<PyResult<Vec<HashMap<String, String>>>>()
+    };
+    let aborted = py.allow_threads(|| RUNTIME.block_on(coroutine))?;
+    Ok(PyTuple::new(py, aborted.iter().map(|r| r.to_object(py))))
+}
+
+/// lists the parts uploaded so far for a multipart upload
+#[pyfunction]
+#[pyo3(signature = (url, mpu, **config))]
+fn s3_list_parts<'py>(
+    py: Python<'py>, url: &str, mpu: &str, config: Option<&PyDict>,
+) -> PyResult<&'py PyTuple> {
+    let config = S3Config::from_kwargs(config)?;
+    let loc = s3_key_path(url)?;
+    let (bucket, key) = loc.parts();
+    let coroutine = async {
+        let s3_client = s3_bucket(&config, bucket, false).await;
+        let mut output: Vec<HashMap<String, String>> = Vec::new();
+        let mut marker: Option<String> = None;
+        loop {
+            let page = s3_client
+                .list_parts()
                 .bucket(bucket)
                 .key(key)
-                .body(ByteStream::from(SdkBody::from(data)))
+                .upload_id(mpu)
+                .set_part_number_marker(marker)
                 .send()
+                .await
+                .map_err(|e| s3_err(url, e))?;
+            for part in page.parts().unwrap_or_default().iter() {
+                let mut h: HashMap<String, String> = HashMap::new();
+                h.insert(
+                    "PartNumber".to_string(),
+                    part.part_number().to_string(),
+                );
+                h.insert(
+                    "ETag".to_string(),
+                    part.e_tag().unwrap_or("").into(),
+                );
+                h.insert("size".to_string(), part.size().to_string());
+                output.push(h);
+            }
+            if !page.is_truncated() {
+                break;
+            }
+            marker = page.next_part_number_marker().map(|t| t.to_string());
+        }
+        Ok::<_, PyErr>(output)
+    };
+    let output = py.allow_threads(|| RUNTIME.block_on(coroutine))?;
+    Ok(PyTuple::new(py, output.iter().map(|r| r.to_object(py))))
+}
+
+use aws_sdk_s3::operation::head_object::HeadObjectError;
+use aws_sdk_s3::operation::put_object::PutObjectOutput;
+use aws_smithy_http::byte_stream::ByteStream;
+
+/// No-copy view of internal buffer of any python object supporting buffers
+#[inline(always)]
+fn py_to_byteslice(value: &PyAny) -> &'static mut [u8] {
+    let buf: PyBuffer<u8> = value.extract().unwrap();
+    unsafe {
+        slice::from_raw_parts_mut(buf.buf_ptr() as *mut u8, buf.len_bytes())
+    }
+}
+
+/// A path and the result of uploading to it
+type Upload = (String, PyResult<HashMap<String, String>>);
+
+/// A buffer's object, held while its data is in use, and the data
+type Buffer = (PyObject, &'static [u8]);
+
+/// The next (path, buffer) pair from a python iterator, if any, with the
+/// buffer's data or why it cannot be used
+fn next_upload(
+    iter: &Py<PyIterator>,
+) -> PyResult<Option<(String, PyResult<Buffer>)>> {
+    Python::with_gil(|py| {
+        let mut iter = iter.as_ref(py);
+        let Some(item) = iter.next() else {
+            return Ok(None);
+        };
+        let (url, value): (String, &PyAny) = item?.extract()?;
+        let data = match PyBuffer::<u8>::get(value) {
+            Ok(_) => Ok((value.into(), &*py_to_byteslice(value))),
+            Err(e) => Err(e),
+        };
+        Ok(Some((url, data)))
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn s3_put_bytes(
+    url: &str, data: &'static [u8], config: &S3Config, enc: &S3Encryption,
+    cond: &WriteCondition, opts: &S3WriteOptions, requester_pays: bool,
+    anon: bool, algorithm: Option<Algorithm>,
+) -> PyResult<HashMap<String, String>> {
+    let loc = s3_key_path(url)?;
+    let (bucket, key) = loc.parts();
+    let sum = algorithm.map(|a| (a, checksum(a, data)));
+    let unsigned_payload = config.disable_payload_signing;
+    let client = s3_bucket(config, bucket, anon).await;
+    let req = client
+        .put_object()
+        .bucket(bucket)
+        .key(key)
+        .set_request_payer(requester_pays.then_some(RequestPayer::Requester))
+        .body(ByteStream::from(SdkBody::from(data)));
+    let req = checksum!(describe!(encrypt!(req, enc), opts, key), &sum);
+    let resp = send_if!(req, cond, anon, unsigned_payload).map_err(|e| {
+        match s3_status(&e) {
+            Some(412) => precondition_err(url),
+            _ => s3_err(url, e),
+        }
+    })?;
+    s3_put_result(url, &sum, &resp)
+}
+
+/// ETag, VersionId (if versioned) and checksum (if sent) of an upload,
+/// checking that S3 recorded the checksum that was sent
+fn s3_put_result(
+    url: &str, sum: &Option<(Algorithm, String)>, resp: &PutObjectOutput,
+) -> PyResult<HashMap<String, String>> {
+    let encrypted = s3_encrypted(
+        resp.server_side_encryption(),
+        resp.sse_customer_algorithm(),
+    );
+    s3_check_upload(
+        url,
+        sum,
+        s3_stored_checksum(
+            resp.checksum_crc32_c(),
+            resp.checksum_sha256(),
+            resp.e_tag(),
+            encrypted,
+        ),
+    )?;
+    let mut h: HashMap<String, String> = HashMap::new();
+    h.insert("ETag".to_string(), resp.e_tag().unwrap_or("").into());
+    if let Some(v) = resp.version_id() {
+        h.insert("VersionId".to_string(), v.into());
+    }
+    if let Some((a, v)) = sum {
+        h.insert(format!("Checksum{}", a.name()), v.clone());
+    }
+    Ok(h)
+}
+
+/// Uploads each of a dict or iterable of (path, buffer), `concurrency` at a
+/// time, pulling from the iterable only as uploads complete
+///
+/// Returns a dict of path: ETag and VersionId (if versioned), or the exception
+/// for that path, in input order; with raise_on_error, raises the first.
+///
+/// if_none_match, if_match: write each only if the key does not exist ("*"),
+/// or has this ETag; else PreconditionFailedError
+///
+/// content_type is guessed from each key's extension, unless given.
+///
+/// checksum_algorithm: "CRC32C", "SHA256" or "MD5" to send a checksum of
+/// each, for S3 to verify and record; raises ChecksumError if S3 disagrees
+#[pyfunction]
+#[pyo3(signature = (data, sse=None, sse_kms_key_id=None, sse_customer_key=None, if_none_match=None, if_match=None, content_type=None, cache_control=None, content_encoding=None, metadata=None, acl=None, storage_class=None, object_lock_mode=None, object_lock_retain_until=None, object_lock_legal_hold=None, concurrency=32, raise_on_error=false, requester_pays=false, anon=false, checksum_algorithm=None, **config))]
+#[allow(clippy::too_many_arguments)]
+fn s3_pipe(
+    py: Python, data: &PyAny, sse: Option<&str>, sse_kms_key_id: Option<&str>,
+    sse_customer_key: Option<&str>, if_none_match: Option<&str>,
+    if_match: Option<&str>, content_type: Option<String>,
+    cache_control: Option<String>, content_encoding: Option<String>,
+    metadata: Option<HashMap<String, String>>, acl: Option<&str>,
+    storage_class: Option<&str>, object_lock_mode: Option<&str>,
+    object_lock_retain_until: Option<f64>,
+    object_lock_legal_hold: Option<bool>, concurrency: usize,
+    raise_on_error: bool, requester_pays: bool, anon: bool,
+    checksum_algorithm: Option<&str>, config: Option<&PyDict>,
+) -> PyResult<PyObject> {
+    let config = S3Config::from_kwargs(config)?;
+    let enc = S3Encryption::new(sse, sse_kms_key_id, sse_customer_key)?;
+    let cond = WriteCondition::new(if_none_match, if_match)?;
+    let algorithm = self::checksum_algorithm(checksum_algorithm)?;
+    let opts = S3WriteOptions::new(
+        content_type,
+        cache_control,
+        content_encoding,
+        metadata,
+        acl,
+        storage_class,
+    )
+    .lock(
+        object_lock_mode,
+        object_lock_retain_until,
+        object_lock_legal_hold,
+    )?;
+    // S3 only takes Object Lock settings with a checksum of the data
+    let algorithm = algorithm.or(opts.locks().then_some(Algorithm::Md5));
+    let items: &PyAny = match data.downcast::<PyDict>() {
+        Ok(d) => d.items().as_ref(),
+        Err(_) => data,
+    };
+    let iter: Py<PyIterator> = items.iter()?.into();
+
+    let coroutine = async {
+        let (config, enc, cond, opts) = (&config, &enc, &cond, &opts);
+        // boxed as a Send stream, for allow_threads to accept it
+        let mut uploads: Pin<
+            Box<dyn Stream<Item = PyResult<Upload>> + Send + '_>,
+        > = Box::pin(
+            futures::stream::unfold(&iter, |iter| async move {
+                next_upload(iter).transpose().map(|item| (item, iter))
+            })
+            .map(|item| async move {
+                let (url, data) = item?;
+                let res = match data {
+                    // holding the buffer's object until done
+                    Ok((_obj, data)) => {
+                        s3_put_bytes(
+                            &url,
+                            data,
+                            config,
+                            enc,
+                            cond,
+                            opts,
+                            requester_pays,
+                            anon,
+                            algorithm,
+                        )
+                        .await
+                    }
+                    Err(e) => Err(e),
+                };
+                Ok::<_, PyErr>((url, res))
+            })
+            .buffered(concurrency.max(1)),
+        );
+        let mut results: Vec<Upload> = Vec::new();
+        while let Some(item) = uploads.next().await {
+            let (url, res) = item?;
+            let res = match res {
+                Err(e) if raise_on_error => return Err(e),
+                res => res,
+            };
+            results.push((url, res));
+        }
+        Ok::<_, PyErr>(results)
+    };
+    let results = py.allow_threads(|| RUNTIME.block_on(coroutine))?;
+    let out = PyDict::new(py);
+    for (url, res) in results {
+        match res {
+            Ok(h) => out.set_item(url, h)?,
+            Err(e) => out.set_item(url, e.into_py(py))?,
+        }
+    }
+    Ok(out.into())
+}
+
+/// checksum of a local file, read in chunks
+async fn file_checksum(
+    lpath: &str, algorithm: Algorithm,
+) -> std::io::Result<String> {
+    let mut file = tokio::fs::File::open(lpath).await?;
+    let mut hasher = algorithm.hasher();
+    let mut buf = vec![0u8; 1 << 20];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finish())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn s3_put_file(
+    lpath: &str, url: &str, config: &S3Config, enc: &S3Encryption,
+    opts: &S3WriteOptions, requester_pays: bool, anon: bool,
+    algorithm: Option<Algorithm>,
+) -> PyResult<HashMap<String, String>> {
+    let loc = s3_key_path(url)?;
+    let (bucket, key) = loc.parts();
+    let sum = match algorithm {
+        Some(a) => Some((
+            a,
+            file_checksum(lpath, a).await.map_err(|e| {
+                PyIOError::new_err(format!("{}: {}", lpath, e))
+            })?,
+        )),
+        None => None,
+    };
+    let s3 = s3_bucket(config, bucket, anon).await;
+    let unsigned_payload = config.disable_payload_signing;
+    let body = ByteStream::from_path(lpath)
+        .await
+        .map_err(|e| PyIOError::new_err(format!("{}: {}", lpath, e)))?;
+    let req = s3
+        .put_object()
+        .bucket(bucket)
+        .key(key)
+        .set_request_payer(requester_pays.then_some(RequestPayer::Requester))
+        .body(body);
+    let resp = send!(
+        checksum!(describe!(encrypt!(req, enc), opts, key), &sum),
+        anon,
+        unsigned_payload
+    )
+    .map_err(|e| s3_err(url, e))?;
+    s3_put_result(url, &sum, &resp)
+}
+
+/// uploads local files to keys, streaming from disk
+///
+/// Returns ETag and VersionId (if versioned) of each upload
+#[pyfunction]
+#[pyo3(signature = (lpaths, paths, sse=None, sse_kms_key_id=None, sse_customer_key=None, content_type=None, cache_control=None, content_encoding=None, metadata=None, acl=None, storage_class=None, object_lock_mode=None, object_lock_retain_until=None, object_lock_legal_hold=None, requester_pays=false, anon=false, checksum_algorithm=None, **config))]
+#[allow(clippy::too_many_arguments)]
+fn s3_put(
+    py: Python, lpaths: Vec<&str>, paths: Vec<&str>, sse: Option<&str>,
+    sse_kms_key_id: Option<&str>, sse_customer_key: Option<&str>,
+    content_type: Option<String>, cache_control: Option<String>,
+    content_encoding: Option<String>,
+    metadata: Option<HashMap<String, String>>, acl: Option<&str>,
+    storage_class: Option<&str>, object_lock_mode: Option<&str>,
+    object_lock_retain_until: Option<f64>,
+    object_lock_legal_hold: Option<bool>, requester_pays: bool, anon: bool,
+    checksum_algorithm: Option<&str>, config: Option<&PyDict>,
+) -> PyResult<Vec<HashMap<String, String>>> {
+    let config = S3Config::from_kwargs(config)?;
+    let enc = S3Encryption::new(sse, sse_kms_key_id, sse_customer_key)?;
+    let algorithm = self::checksum_algorithm(checksum_algorithm)?;
+    let opts = S3WriteOptions::new(
+        content_type,
+        cache_control,
+        content_encoding,
+        metadata,
+        acl,
+        storage_class,
+    )
+    .lock(
+        object_lock_mode,
+        object_lock_retain_until,
+        object_lock_legal_hold,
+    )?;
+    // S3 only takes Object Lock settings with a checksum of the data
+    let algorithm = algorithm.or(opts.locks().then_some(Algorithm::Md5));
+    let coroutine = async {
+        join_all(lpaths.iter().zip(paths).map(|(l, u)| {
+            s3_put_file(
+                l,
+                u,
+                &config,
+                &enc,
+                &opts,
+                requester_pays,
+                anon,
+                algorithm,
+            )
         }))
-        .await;
-        // convert results, giving error or e-tag in original order
-        let res: Vec<String> = results
-            .drain(..)
-            .map(|r| match r {
-                Ok(res) => res.e_tag().unwrap().into(),
-                Err(e) => format!("S3 ERROR: {}", e),
+        .await
+        .into_iter()
+        .collect::<PyResult<Vec<HashMap<String, String>>>>()
+    };
+    py.allow_threads(|| RUNTIME.block_on(coroutine))
+}
+
+/// Largest object a single CopyObject request can copy
+const COPY_MAX: i64 = 5 * 1024 * 1024 * 1024;
+/// Part size for multipart copies, raised if needed to stay within the
+/// 10,000 parts S3 allows
+const COPY_PART: i64 = 512 * 1024 * 1024;
+
+/// server-side copy of one key to another, returning the new VersionId;
+/// objects over 5 GiB are copied in parts with UploadPartCopy
+///
+/// With SSE-C, the source is assumed to use the same customer key.
+#[pyfunction]
+#[pyo3(signature = (path1, path2, sse=None, sse_kms_key_id=None, sse_customer_key=None, requester_pays=false, anon=false, checksum_algorithm=None, **config))]
+#[allow(clippy::too_many_arguments)]
+fn s3_copy(
+    py: Python, path1: &str, path2: &str, sse: Option<&str>,
+    sse_kms_key_id: Option<&str>, sse_customer_key: Option<&str>,
+    requester_pays: bool, anon: bool, checksum_algorithm: Option<&str>,
+    config: Option<&PyDict>,
+) -> PyResult<Option<String>> {
+    let config = S3Config::from_kwargs(config)?;
+    let algorithm = self::checksum_algorithm(checksum_algorithm)?;
+    let enc = S3Encryption::new(sse, sse_kms_key_id, sse_customer_key)?;
+    let loc1 = s3_key_path(path1)?;
+    let (bucket1, key1) = loc1.parts();
+    let loc2 = s3_key_path(path2)?;
+    let (bucket2, key2) = loc2.parts();
+    let payer = requester_pays.then_some(RequestPayer::Requester);
+    let coroutine = async {
+        let source = s3_bucket(&config, bucket1, anon).await;
+        let req = source
+            .head_object()
+            .bucket(bucket1)
+            .key(key1)
+            .set_request_payer(payer.clone());
+        let head = send!(encrypt!(req, enc, customer), anon)
+            .map_err(|e| s3_err(path1, e))?;
+        let s3_client = s3_bucket(&config, bucket2, anon).await;
+        if head.content_length() > COPY_MAX {
+            return s3_copy_parts(
+                &s3_client,
+                (bucket1, key1),
+                (bucket2, key2),
+                head.content_length(),
+                &enc,
+                algorithm,
+                payer,
+                anon,
+            )
+            .await
+            .map_err(|e| {
+                PyRuntimeError::new_err(format!("S3 ERROR: {}: {}", path2, e))
+            });
+        }
+        let req = s3_client
+            .copy_object()
+            .copy_source(format!("{}/{}", bucket1, encode(key1)))
+            .bucket(bucket2)
+            .key(key2)
+            .set_checksum_algorithm(s3_checksum_algorithm(algorithm))
+            .set_request_payer(
+                requester_pays.then_some(RequestPayer::Requester),
+            );
+        let req = encrypt!(req, enc)
+            .set_copy_source_sse_customer_algorithm(enc.customer_algorithm())
+            .set_copy_source_sse_customer_key(enc.customer_key.clone())
+            .set_copy_source_sse_customer_key_md5(
+                enc.customer_key_md5.clone(),
+            );
+        let resp = send!(req, anon).map_err(|e| s3_err(path1, e))?;
+        Ok(resp.version_id().map(|v| v.to_string()))
+    };
+    py.allow_threads(|| RUNTIME.block_on(coroutine))
+}
+
+/// Copy a large object as a multipart upload of ranges of the source,
+/// aborting the upload if any part fails
+#[allow(clippy::too_many_arguments)]
+async fn s3_copy_parts(
+    s3_client: &Client, (bucket1, key1): (&str, &str),
+    (bucket2, key2): (&str, &str), size: i64, enc: &S3Encryption,
+    algorithm: Option<Algorithm>, payer: Option<RequestPayer>, anon: bool,
+) -> Result<Option<String>, String> {
+    let req = s3_client
+        .create_multipart_upload()
+        .bucket(bucket2)
+        .key(key2)
+        .set_checksum_algorithm(s3_checksum_algorithm(algorithm))
+        .set_request_payer(payer.clone());
+    let mpu = send!(encrypt!(req, enc), anon)
+        .map_err(|e| DisplayErrorContext(e).to_string())?;
+    let mpu = mpu.upload_id().unwrap_or_default();
+    let part_size = COPY_PART.max((size + 9_999) / 10_000);
+    let ranges: Vec<(i32, i64)> = (0..size)
+        .step_by(part_size as usize)
+        .zip(1..)
+        .map(|(s, n)| (n, s))
+        .collect();
+    let parts = futures::stream::iter(ranges)
+        .map(|(part, start)| {
+            let end = (start + part_size).min(size) - 1;
+            let req = s3_client
+                .upload_part_copy()
+                .copy_source(format!("{}/{}", bucket1, encode(key1)))
+                .copy_source_range(format!("bytes={}-{}", start, end))
+                .bucket(bucket2)
+                .key(key2)
+                .upload_id(mpu)
+                .part_number(part)
+                .set_request_payer(payer.clone());
+            let req = encrypt!(req, enc, customer)
+                .set_copy_source_sse_customer_algorithm(
+                    enc.customer_algorithm(),
+                )
+                .set_copy_source_sse_customer_key(enc.customer_key.clone())
+                .set_copy_source_sse_customer_key_md5(
+                    enc.customer_key_md5.clone(),
+                );
+            async move {
+                let r = send!(req, anon)
+                    .map_err(|e| DisplayErrorContext(e).to_string())?;
+                let r = r.copy_part_result();
+                Ok(CompletedPart::builder()
+                    .set_e_tag(r.and_then(|r| r.e_tag()).map(Into::into))
+                    .part_number(part)
+                    .set_checksum_crc32_c(
+                        r.and_then(|r| r.checksum_crc32_c()).map(Into::into),
+                    )
+                    .set_checksum_sha256(
+                        r.and_then(|r| r.checksum_sha256()).map(Into::into),
+                    )
+                    .build())
+            }
+        })
+        .buffered(8)
+        .collect::<Vec<Result<CompletedPart, String>>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<CompletedPart>, String>>();
+    let req = match parts {
+        Ok(parts) => s3_client
+            .complete_multipart_upload()
+            .bucket(bucket2)
+            .key(key2)
+            .upload_id(mpu)
+            .set_request_payer(payer.clone())
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(parts))
+                    .build(),
+            ),
+        Err(e) => {
+            let req = s3_client
+                .abort_multipart_upload()
+                .bucket(bucket2)
+                .key(key2)
+                .upload_id(mpu)
+                .set_request_payer(payer);
+            // the copy's own error is the one to report
+            let _ = send!(req, anon);
+            return Err(e);
+        }
+    };
+    let r =
+        send!(req, anon).map_err(|e| DisplayErrorContext(e).to_string())?;
+    Ok(r.version_id().map(|v| v.to_string()))
+}
+
+/// delete a key (or a version of it), which need not exist
+#[pyfunction]
+#[pyo3(signature = (path, version_id=None, requester_pays=false, anon=false, **config))]
+fn s3_delete(
+    py: Python, path: &str, version_id: Option<String>, requester_pays: bool,
+    anon: bool, config: Option<&PyDict>,
+) -> PyResult<()> {
+    let config = S3Config::from_kwargs(config)?;
+    let loc = s3_key_path(path)?;
+    let (bucket, key) = loc.parts();
+    let coroutine = async {
+        let s3_client = s3_bucket(&config, bucket, anon).await;
+        let req = s3_client
+            .delete_object()
+            .bucket(bucket)
+            .key(key)
+            .set_version_id(version_id)
+            .set_request_payer(
+                requester_pays.then_some(RequestPayer::Requester),
+            );
+        send!(req, anon).map_err(|e| s3_err(path, e))
+    };
+    py.allow_threads(|| RUNTIME.block_on(coroutine))?;
+    Ok(())
+}
+
+/// checksum of an object's data, base64-encoded: as recorded by S3, if it
+/// has one for this algorithm ("CRC32C", "SHA256" or "MD5"), else computed
+/// by streaming the object
+#[pyfunction]
+#[pyo3(signature = (path, algorithm="CRC32C", version_id=None, anon=false, requester_pays=false, sse_customer_key=None, **config))]
+#[allow(clippy::too_many_arguments)]
+fn s3_checksum(
+    py: Python, path: &str, algorithm: &str, version_id: Option<String>,
+    anon: bool, requester_pays: bool, sse_customer_key: Option<&str>,
+    config: Option<&PyDict>,
+) -> PyResult<String> {
+    let config = S3Config::from_kwargs(config)?;
+    let algorithm = checksum_algorithm(Some(algorithm))?.unwrap();
+    let loc = s3_key_path(path)?;
+    let (bucket, key) = loc.parts();
+    let enc = S3Encryption::new(None, None, sse_customer_key)?;
+    let payer = requester_pays.then_some(RequestPayer::Requester);
+    let coroutine = async {
+        let s3 = s3_bucket(&config, bucket, anon).await;
+        let req = s3
+            .head_object()
+            .bucket(bucket)
+            .key(key)
+            .set_version_id(version_id.clone())
+            .set_request_payer(payer.clone())
+            .checksum_mode(ChecksumMode::Enabled);
+        let head = send!(encrypt!(req, enc, customer), anon)
+            .map_err(|e| s3_err(path, e))?;
+        let stored = s3_stored_checksum(
+            head.checksum_crc32_c(),
+            head.checksum_sha256(),
+            head.e_tag(),
+            s3_encrypted(
+                head.server_side_encryption(),
+                head.sse_customer_algorithm(),
+            ),
+        );
+        if let Some((a, sum)) = stored {
+            if a == algorithm {
+                return Ok(sum);
+            }
+        }
+        let req = s3
+            .get_object()
+            .bucket(bucket)
+            .key(key)
+            .set_version_id(version_id)
+            .set_request_payer(payer)
+            .set_if_match(head.e_tag().map(|e| e.to_string()));
+        let mut body = send!(encrypt!(req, enc, customer), anon)
+            .map_err(|e| s3_err(path, e))?
+            .body;
+        let mut hasher = algorithm.hasher();
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk.map_err(|e| {
+                PyIOError::new_err(format!("S3 ERROR: {}: {}", path, e))
+            })?;
+            hasher.update(chunk.as_ref());
+        }
+        Ok(hasher.finish())
+    };
+    py.allow_threads(|| RUNTIME.block_on(coroutine))
+}
+
+/// the tags of an object, as a dict
+#[pyfunction]
+#[pyo3(signature = (path, version_id=None, **config))]
+fn s3_get_tags(
+    py: Python, path: &str, version_id: Option<String>,
+    config: Option<&PyDict>,
+) -> PyResult<HashMap<String, String>> {
+    let config = S3Config::from_kwargs(config)?;
+    let loc = s3_key_path(path)?;
+    let (bucket, key) = loc.parts();
+    let coroutine = async {
+        let s3_client = s3_bucket(&config, bucket, false).await;
+        s3_client
+            .get_object_tagging()
+            .bucket(bucket)
+            .key(key)
+            .set_version_id(version_id)
+            .send()
+            .await
+            .map_err(|e| s3_err(path, e))
+    };
+    let resp = py.allow_threads(|| RUNTIME.block_on(coroutine))?;
+    Ok(resp
+        .tag_set()
+        .unwrap_or_default()
+        .iter()
+        .map(|t| {
+            (
+                t.key().unwrap_or("").to_string(),
+                t.value().unwrap_or("").to_string(),
+            )
+        })
+        .collect())
+}
+
+/// replace the tags of an object
+#[pyfunction]
+#[pyo3(signature = (path, tags, version_id=None, **config))]
+fn s3_put_tags(
+    py: Python, path: &str, tags: HashMap<String, String>,
+    version_id: Option<String>, config: Option<&PyDict>,
+) -> PyResult<()> {
+    let config = S3Config::from_kwargs(config)?;
+    let loc = s3_key_path(path)?;
+    let (bucket, key) = loc.parts();
+    let tagging = Tagging::builder()
+        .set_tag_set(Some(
+            tags.into_iter()
+                .map(|(k, v)| Tag::builder().key(k).value(v).build())
+                .collect(),
+        ))
+        .build();
+    let coroutine = async {
+        let s3_client = s3_bucket(&config, bucket, false).await;
+        s3_client
+            .put_object_tagging()
+            .bucket(bucket)
+            .key(key)
+            .set_version_id(version_id)
+            .tagging(tagging)
+            .send()
+            .await
+            .map_err(|e| s3_err(path, e))
+    };
+    py.allow_threads(|| RUNTIME.block_on(coroutine))?;
+    Ok(())
+}
+
+/// whether a failed request found no Object Lock setting on the object
+fn s3_no_lock<E>(err: &SdkError<E>) -> bool
+where
+    E: ProvideErrorMetadata,
+{
+    s3_service_err(err).and_then(|e| e.code())
+        == Some("NoSuchObjectLockConfiguration")
+}
+
+/// the Object Lock retention of an object: Mode ("GOVERNANCE" or
+/// "COMPLIANCE") and RetainUntilDate, or empty if it has none
+#[pyfunction]
+#[pyo3(signature = (path, version_id=None, **config))]
+fn s3_get_retention(
+    py: Python, path: &str, version_id: Option<String>,
+    config: Option<&PyDict>,
+) -> PyResult<HashMap<String, String>> {
+    let config = S3Config::from_kwargs(config)?;
+    let loc = s3_key_path(path)?;
+    let (bucket, key) = loc.parts();
+    let coroutine = async {
+        let s3_client = s3_bucket(&config, bucket, false).await;
+        let resp = s3_client
+            .get_object_retention()
+            .bucket(bucket)
+            .key(key)
+            .set_version_id(version_id)
+            .send()
+            .await;
+        match resp {
+            Ok(r) => Ok(r.retention().cloned()),
+            Err(e) if s3_no_lock(&e) => Ok(None),
+            Err(e) => Err(s3_err(path, e)),
+        }
+    };
+    let retention = py.allow_threads(|| RUNTIME.block_on(coroutine))?;
+    let mut h: HashMap<String, String> = HashMap::new();
+    if let Some(r) = retention {
+        if let Some(m) = r.mode() {
+            h.insert("Mode".to_string(), m.as_str().to_string());
+        }
+        if r.retain_until_date().is_some() {
+            h.insert(
+                "RetainUntilDate".to_string(),
+                s3_time(r.retain_until_date()),
+            );
+        }
+    }
+    Ok(h)
+}
+
+/// set the Object Lock retention of an object: mode, "GOVERNANCE" or
+/// "COMPLIANCE", until retain_until, in epoch seconds; with neither, remove
+/// it. Shortening or removing GOVERNANCE retention needs bypass_governance
+#[pyfunction]
+#[pyo3(signature = (path, mode=None, retain_until=None, version_id=None, bypass_governance=false, **config))]
+fn s3_put_retention(
+    py: Python, path: &str, mode: Option<&str>, retain_until: Option<f64>,
+    version_id: Option<String>, bypass_governance: bool,
+    config: Option<&PyDict>,
+) -> PyResult<()> {
+    let config = S3Config::from_kwargs(config)?;
+    let loc = s3_key_path(path)?;
+    let (bucket, key) = loc.parts();
+    // validated as for writes
+    let lock = S3WriteOptions::default().lock(mode, retain_until, None)?;
+    let retention = ObjectLockRetention::builder()
+        .set_mode(
+            lock.lock_mode.map(|m| ObjectLockRetentionMode::from(m.as_str())),
+        )
+        .set_retain_until_date(lock.retain_until)
+        .build();
+    let coroutine = async {
+        let s3_client = s3_bucket(&config, bucket, false).await;
+        s3_client
+            .put_object_retention()
+            .bucket(bucket)
+            .key(key)
+            .set_version_id(version_id)
+            .retention(retention)
+            .set_bypass_governance_retention(bypass_governance.then_some(true))
+            .send()
+            .await
+            .map_err(|e| s3_err(path, e))
+    };
+    py.allow_threads(|| RUNTIME.block_on(coroutine))?;
+    Ok(())
+}
+
+/// whether an object is under an Object Lock legal hold
+#[pyfunction]
+#[pyo3(signature = (path, version_id=None, **config))]
+fn s3_get_legal_hold(
+    py: Python, path: &str, version_id: Option<String>,
+    config: Option<&PyDict>,
+) -> PyResult<bool> {
+    let config = S3Config::from_kwargs(config)?;
+    let loc = s3_key_path(path)?;
+    let (bucket, key) = loc.parts();
+    let coroutine = async {
+        let s3_client = s3_bucket(&config, bucket, false).await;
+        let resp = s3_client
+            .get_object_legal_hold()
+            .bucket(bucket)
+            .key(key)
+            .set_version_id(version_id)
+            .send()
+            .await;
+        match resp {
+            Ok(r) => Ok(r.legal_hold().and_then(|h| h.status())
+                == Some(&ObjectLockLegalHoldStatus::On)),
+            Err(e) if s3_no_lock(&e) => Ok(false),
+            Err(e) => Err(s3_err(path, e)),
+        }
+    };
+    py.allow_threads(|| RUNTIME.block_on(coroutine))
+}
+
+/// place or lift an Object Lock legal hold on an object
+#[pyfunction]
+#[pyo3(signature = (path, on, version_id=None, **config))]
+fn s3_put_legal_hold(
+    py: Python, path: &str, on: bool, version_id: Option<String>,
+    config: Option<&PyDict>,
+) -> PyResult<()> {
+    let config = S3Config::from_kwargs(config)?;
+    let loc = s3_key_path(path)?;
+    let (bucket, key) = loc.parts();
+    let status = match on {
+        true => ObjectLockLegalHoldStatus::On,
+        false => ObjectLockLegalHoldStatus::Off,
+    };
+    let coroutine = async {
+        let s3_client = s3_bucket(&config, bucket, false).await;
+        s3_client
+            .put_object_legal_hold()
+            .bucket(bucket)
+            .key(key)
+            .set_version_id(version_id)
+            .legal_hold(ObjectLockLegalHold::builder().status(status).build())
+            .send()
+            .await
+            .map_err(|e| s3_err(path, e))
+    };
+    py.allow_threads(|| RUNTIME.block_on(coroutine))?;
+    Ok(())
+}
+
+/// a URL giving access to "bucket/key" with the given HTTP method (GET, HEAD,
+/// PUT or DELETE) for `expiration` seconds, without credentials
+#[pyfunction]
+#[pyo3(signature = (path, expiration=100.0, method="GET", version_id=None, requester_pays=false, **config))]
+fn s3_sign(
+    py: Python, path: &str, expiration: f64, method: &str,
+    version_id: Option<String>, requester_pays: bool, config: Option<&PyDict>,
+) -> PyResult<String> {
+    let config = S3Config::from_kwargs(config)?;
+    let loc = s3_key_path(path)?;
+    let (bucket, key) = loc.parts();
+    let presign = PresigningConfig::expires_in(secs(expiration))
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+    let payer = requester_pays.then_some(RequestPayer::Requester);
+    let coroutine = async {
+        let s3_client = s3_bucket(&config, bucket, false).await;
+        let signed = match method.to_uppercase().as_str() {
+            "GET" => s3_client
+                .get_object()
+                .bucket(bucket)
+                .key(key)
+                .set_version_id(version_id)
+                .set_request_payer(payer)
+                .presigned(presign)
+                .await
+                .map_err(|e| s3_err(path, e))?,
+            "HEAD" => s3_client
+                .head_object()
+                .bucket(bucket)
+                .key(key)
+                .set_version_id(version_id)
+                .set_request_payer(payer)
+                .presigned(presign)
+                .await
+                .map_err(|e| s3_err(path, e))?,
+            "PUT" => s3_client
+                .put_object()
+                .bucket(bucket)
+                .key(key)
+                .set_request_payer(payer)
+                .presigned(presign)
+                .await
+                .map_err(|e| s3_err(path, e))?,
+            "DELETE" => s3_client
+                .delete_object()
+                .bucket(bucket)
+                .key(key)
+                .set_version_id(version_id)
+                .set_request_payer(payer)
+                .presigned(presign)
+                .await
+                .map_err(|e| s3_err(path, e))?,
+            other => {
+                return Err(PyValueError::new_err(format!(
+                    "cannot sign method {}",
+                    other
+                )))
+            }
+        };
+        Ok(signed.uri().to_string())
+    };
+    py.allow_threads(|| RUNTIME.block_on(coroutine))
+}
+
+/// Which state of an object a read must see
+#[derive(Clone, Default)]
+struct S3ReadPin {
+    version_id: Option<String>,
+    if_match: Option<String>,
+    /// seconds since the epoch
+    if_unmodified_since: Option<f64>,
+}
+
+/// The bytes of the range. A failed if_match or if_unmodified_since
+/// condition raises FileChangedError, so that callers reading a file in
+/// pieces never combine bytes from before and after it changed.
+/// With verify, a whole object is checked against its stored checksum
+#[allow(clippy::too_many_arguments)]
+async fn s3_get_one_range(
+    url: &str, config: &S3Config, start: i64, end: i64, requester_pays: bool,
+    anon: bool, pin: S3ReadPin, enc: &S3Encryption, verify: bool,
+) -> PyResult<Vec<u8>> {
+    let loc = s3_key_path(url)?;
+    let (bucket, key) = loc.parts();
+    let s3 = s3_bucket(config, bucket, anon).await;
+    let mut resp = s3
+        .get_object()
+        .bucket(bucket)
+        .key(key)
+        .set_version_id(pin.version_id)
+        .set_if_match(pin.if_match.clone())
+        .set_if_unmodified_since(
+            pin.if_unmodified_since.map(DateTime::from_secs_f64),
+        );
+    if (start != 0) | (end != 0) {
+        let range = match end {
+            0 => format!("bytes={}", start), // None/not set
+            -1 => format!("bytes={}", start), // to the end
+            _ => format!("bytes={}-{}", start, end),
+        };
+        resp = resp.set_range(Some(range))
+    } else if verify {
+        resp = resp.checksum_mode(ChecksumMode::Enabled)
+    };
+    if requester_pays {
+        resp = resp.set_request_payer(Some(RequestPayer::Requester));
+    }
+    resp = encrypt!(resp, enc, customer);
+    let resp = send!(resp, anon);
+    if resp.as_ref().err().and_then(s3_status) == Some(412) {
+        return Err(FileChangedError::new_err(format!(
+            "{} changed since it was opened (ETag {})",
+            url,
+            pin.if_match.unwrap_or_default()
+        )));
+    }
+    if let Some(e) = resp
+        .as_ref()
+        .err()
+        .and_then(s3_service_err)
+        .and_then(|e| archived_err(url, e))
+    {
+        return Err(e);
+    }
+    let bytes = match resp {
+        // Convert the body into a bytes vec
+        Ok(r) => {
+            let stored = match verify && start == 0 && end == 0 {
+                true => s3_stored_checksum(
+                    r.checksum_crc32_c(),
+                    r.checksum_sha256(),
+                    r.e_tag(),
+                    s3_encrypted(
+                        r.server_side_encryption(),
+                        r.sse_customer_algorithm(),
+                    ),
+                ),
+                false => None,
+            };
+            let data = r
+                .body
+                .collect()
+                .await
+                .map_err(|e| {
+                    PyIOError::new_err(format!("S3 ERROR: {}: {}", url, e))
+                })?
+                .into_bytes()
+                .to_vec();
+            if let Some((algorithm, expected)) = stored {
+                let actual = checksum(algorithm, &data);
+                if actual != expected {
+                    return Err(checksum_err(
+                        url, algorithm, &expected, &actual,
+                    ));
+                }
+            }
+            data
+        }
+        Err(e) => return Err(s3_err(url, e)),
+    };
+    Ok(bytes)
+}
+
+/// With verify, the file is checked against the object's stored checksum,
+/// and removed if it does not match
+#[allow(clippy::too_many_arguments)]
+async fn s3_get_file(
+    url: &str, lpath: &str, config: &S3Config, version_id: Option<String>,
+    requester_pays: bool, anon: bool, enc: &S3Encryption, verify: bool,
+) -> PyResult<()> {
+    let loc = s3_key_path(url)?;
+    let (bucket, key) = loc.parts();
+    let s3 = s3_bucket(config, bucket, anon).await;
+    let mut resp =
+        s3.get_object().bucket(bucket).key(key).set_version_id(version_id);
+    if requester_pays {
+        resp = resp.request_payer(RequestPayer::Requester);
+    }
+    if verify {
+        resp = resp.checksum_mode(ChecksumMode::Enabled);
+    }
+    resp = encrypt!(resp, enc, customer);
+    let resp = send!(resp, anon).map_err(|e| {
+        let archived = s3_service_err(&e).and_then(|se| archived_err(url, se));
+        archived.unwrap_or_else(|| s3_err(url, e))
+    })?;
+    let stored = match verify {
+        true => s3_stored_checksum(
+            resp.checksum_crc32_c(),
+            resp.checksum_sha256(),
+            resp.e_tag(),
+            s3_encrypted(
+                resp.server_side_encryption(),
+                resp.sse_customer_algorithm(),
+            ),
+        ),
+        false => None,
+    };
+    let mut hasher = stored.as_ref().map(|(a, _)| a.hasher());
+    let mut body = resp.body;
+    let mut out = tokio::fs::File::create(lpath).await?;
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|e| {
+            PyRuntimeError::new_err(format!("S3 ERROR: {}: {}", url, e))
+        })?;
+        if let Some(h) = hasher.as_mut() {
+            h.update(chunk.as_ref());
+        }
+        out.write_all(chunk.as_ref()).await?;
+    }
+    out.flush().await?;
+    if let (Some((algorithm, expected)), Some(h)) = (stored, hasher) {
+        let actual = h.finish();
+        if actual != expected {
+            tokio::fs::remove_file(lpath).await.ok();
+            return Err(checksum_err(url, algorithm, &expected, &actual));
+        }
+    }
+    Ok(())
+}
+
+/// downloads keys to local files, streaming
+///
+/// verify: check each against its stored checksum, raising ChecksumError
+#[pyfunction]
+#[pyo3(signature = (paths, lpaths, version_id=None, anon=false, requester_pays=false, sse_customer_key=None, verify=false, **config))]
+#[allow(clippy::too_many_arguments)]
+fn s3_get(
+    py: Python, paths: Vec<&str>, lpaths: Vec<&str>,
+    version_id: Option<Vec<Option<String>>>, anon: bool, requester_pays: bool,
+    sse_customer_key: Option<&str>, verify: bool, config: Option<&PyDict>,
+) -> PyResult<()> {
+    let config = S3Config::from_kwargs(config)?;
+    let versions = version_id.unwrap_or_else(|| vec![None; paths.len()]);
+    let enc = S3Encryption::new(None, None, sse_customer_key)?;
+    let coroutine = async {
+        join_all(paths.iter().zip(lpaths).zip(versions).map(|((u, l), v)| {
+            s3_get_file(u, l, &config, v, requester_pays, anon, &enc, verify)
+        }))
+        .await
+        .into_iter()
+        .collect::<PyResult<Vec<()>>>()
+    };
+    py.allow_threads(|| RUNTIME.block_on(coroutine))?;
+    Ok(())
+}
+
+/// format an S3 timestamp as ISO8601, or empty if not given
+fn s3_time(t: Option<&DateTime>) -> String {
+    t.and_then(|t| t.fmt(DateTimeFormat::DateTime).ok())
+        .unwrap_or_default()
+}
+
+/// lists every version and delete marker below some root key prefix
+#[pyfunction]
+#[pyo3(signature = (path, anon=false, requester_pays=false, **config))]
+fn s3_ls_versions<'py>(
+    py: Python<'py>, path: &str, anon: bool, requester_pays: bool,
+    config: Option<&PyDict>,
+) -> PyResult<&'py PyTuple> {
+    let config = S3Config::from_kwargs(config)?;
+    // ListObjectVersions takes no request payer in this SDK version
+    let _ = requester_pays;
+    let loc = s3_path(path)?;
+    let (bucket, key) = loc.parts();
+    let coroutine = async {
+        let s3_client = s3_bucket(&config, bucket, anon).await;
+        let mut output: Vec<HashMap<String, String>> = Vec::new();
+        let mut key_marker: Option<String> = None;
+        let mut version_marker: Option<String> = None;
+        loop {
+            let resp = s3_client
+                .list_object_versions()
+                .bucket(bucket)
+                .prefix(key)
+                .set_key_marker(key_marker)
+                .set_version_id_marker(version_marker);
+            let page = send!(resp, anon).map_err(|e| s3_err(path, e))?;
+            for ob in page.versions().unwrap_or_default().iter() {
+                let mut h: HashMap<String, String> = HashMap::new();
+                h.insert(
+                    "name".to_string(),
+                    format!("{}/{}", bucket, ob.key().unwrap()),
+                );
+                h.insert("size".to_string(), ob.size().to_string());
+                h.insert("type".to_string(), "file".to_string());
+                h.insert(
+                    "VersionId".to_string(),
+                    ob.version_id().unwrap_or("null").to_string(),
+                );
+                h.insert("IsLatest".to_string(), ob.is_latest().to_string());
+                h.insert("ETag".to_string(), ob.e_tag().unwrap_or("").into());
+                h.insert(
+                    "LastModified".to_string(),
+                    s3_time(ob.last_modified()),
+                );
+                output.push(h)
+            }
+            for ob in page.delete_markers().unwrap_or_default().iter() {
+                let mut h: HashMap<String, String> = HashMap::new();
+                h.insert(
+                    "name".to_string(),
+                    format!("{}/{}", bucket, ob.key().unwrap()),
+                );
+                h.insert("size".to_string(), "0".to_string());
+                h.insert("type".to_string(), "delete-marker".to_string());
+                h.insert(
+                    "VersionId".to_string(),
+                    ob.version_id().unwrap_or("null").to_string(),
+                );
+                h.insert("IsLatest".to_string(), ob.is_latest().to_string());
+                h.insert(
+                    "LastModified".to_string(),
+                    s3_time(ob.last_modified()),
+                );
+                output.push(h)
+            }
+            if !page.is_truncated() {
+                break;
+            }
+            key_marker = page.next_key_marker().map(|t| t.to_string());
+            version_marker =
+                page.next_version_id_marker().map(|t| t.to_string());
+        }
+        Ok::<_, PyErr>(output)
+    };
+    let output = py.allow_threads(|| RUNTIME.block_on(coroutine))?;
+    Ok(PyTuple::new(py, output.iter().map(|r| r.to_object(py))))
+}
+
+/// One page of a bucket listing: keys, common prefixes and where to resume
+struct ListPage {
+    objects: Vec<Object>,
+    prefixes: Vec<String>,
+    next: Option<String>,
+}
+
+/// Options shared by the listing helpers
+#[derive(Clone, Copy, Default)]
+struct ListOpts<'a> {
+    delimiter: Option<&'a str>,
+    max_keys: Option<i32>,
+    /// key to list from, exclusive
+    start_after: Option<&'a str>,
+    requester_pays: bool,
+    anon: bool,
+    /// concurrent listings for long flat listings; 0 or 1 for one at a time
+    parallelism: usize,
+}
+
+/// List one page of keys below prefix with ListObjectsV2 or, for stores only
+/// supporting the original API (`list_version=1`), ListObjects. `token` is
+/// the `next` of the previous page.
+async fn s3_list_page(
+    s3: &Client, config: &S3Config, bucket: &str, prefix: &str,
+    opts: &ListOpts<'_>, token: Option<String>,
+) -> PyResult<ListPage> {
+    let path = format!("{}/{}", bucket, prefix);
+    let payer = opts.requester_pays.then_some(RequestPayer::Requester);
+    let delimiter = opts.delimiter.map(|d| d.to_string());
+    if config.list_version == Some(1) {
+        let req = s3
+            .list_objects()
+            .bucket(bucket)
+            .prefix(prefix)
+            .set_delimiter(delimiter)
+            .set_max_keys(opts.max_keys)
+            .set_marker(token.or(opts.start_after.map(|s| s.to_string())))
+            .set_request_payer(payer);
+        let page = send!(req, opts.anon).map_err(|e| s3_err(&path, e))?;
+        let objects = page.contents().unwrap_or_default().to_vec();
+        let prefixes: Vec<String> = page
+            .common_prefixes()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|p| p.prefix().map(|p| p.to_string()))
+            .collect();
+        // NextMarker is only returned with a delimiter; otherwise resume
+        // after the last key seen
+        let next = if page.is_truncated() {
+            page.next_marker().map(|m| m.to_string()).or_else(|| {
+                let last = objects.last().and_then(|o| o.key());
+                last.into_iter()
+                    .chain(prefixes.last().map(|p| p.as_str()))
+                    .max()
+                    .map(|k| k.to_string())
             })
+        } else {
+            None
+        };
+        Ok(ListPage { objects, prefixes, next })
+    } else {
+        let req = s3
+            .list_objects_v2()
+            .bucket(bucket)
+            .prefix(prefix)
+            .set_delimiter(delimiter)
+            .set_max_keys(opts.max_keys)
+            .set_start_after(opts.start_after.map(|s| s.to_string()))
+            .set_continuation_token(token)
+            .set_request_payer(payer);
+        let page = send!(req, opts.anon).map_err(|e| s3_err(&path, e))?;
+        Ok(ListPage {
+            objects: page.contents().unwrap_or_default().to_vec(),
+            prefixes: page
+                .common_prefixes()
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|p| p.prefix().map(|p| p.to_string()))
+                .collect(),
+            next: page.next_continuation_token().map(|t| t.to_string()),
+        })
+    }
+}
+
+/// Every key and common prefix below prefix, following all pages
+async fn s3_list_all(
+    s3: &Client, config: &S3Config, bucket: &str, prefix: &str,
+    opts: &ListOpts<'_>,
+) -> PyResult<ListPage> {
+    let mut out = ListPage { objects: vec![], prefixes: vec![], next: None };
+    loop {
+        let page =
+            s3_list_page(s3, config, bucket, prefix, opts, out.next).await?;
+        out.objects.extend(page.objects);
+        out.prefixes.extend(page.prefixes);
+        out.next = page.next;
+        if out.next.is_none() {
+            return Ok(out);
+        }
+    }
+}
+
+/// Keys below prefix from after opts.start_after up to and including upto
+async fn s3_list_range(
+    s3: &Client, config: &S3Config, bucket: &str, prefix: &str,
+    opts: &ListOpts<'_>, upto: Option<&str>,
+) -> PyResult<Vec<Object>> {
+    let opts = ListOpts { delimiter: None, ..*opts };
+    let mut objects: Vec<Object> = Vec::new();
+    let mut next: Option<String> = None;
+    loop {
+        let page =
+            s3_list_page(s3, config, bucket, prefix, &opts, next).await?;
+        objects.extend(page.objects);
+        if let Some(upto) = upto {
+            // there is no end key for listings, so stop on passing upto
+            if let Some(i) =
+                objects.iter().position(|o| o.key().unwrap_or("") > upto)
+            {
+                objects.truncate(i);
+                return Ok(objects);
+            }
+        }
+        next = page.next;
+        if next.is_none() {
+            return Ok(objects);
+        }
+    }
+}
+
+/// The first character whose UTF-8 encoding starts with a byte of at least
+/// b: keys sort by their bytes, so those from this one on start with b or
+/// greater
+fn utf8_bound(b: u8) -> char {
+    let c = match b {
+        0..=0x7f => b as u32,
+        0x80..=0xc2 => 0x80,
+        0xc3..=0xdf => ((b - 0xc0) as u32) << 6,
+        0xe0..=0xef => (((b - 0xe0) as u32) << 12).max(0x800),
+        _ => (((b.min(0xf4) - 0xf0) as u32) << 18).max(0x10000),
+    };
+    char::from_u32(c).unwrap_or(char::MAX)
+}
+
+/// Every key below prefix, as from a flat listing, but fetched by up to
+/// opts.parallelism concurrent listings. The prefix's directories are listed
+/// separately if a delimited listing finds several in one page; otherwise
+/// the key space is split evenly by the byte following the prefix (or its
+/// only directory).
+async fn s3_list_parallel(
+    s3: &Client, config: &S3Config, bucket: &str, prefix: &str,
+    opts: &ListOpts<'_>,
+) -> PyResult<Vec<Object>> {
+    let delimited = ListOpts { delimiter: Some("/"), ..*opts };
+    let first =
+        s3_list_page(s3, config, bucket, prefix, &delimited, None).await?;
+    // (prefix, exclusive start, inclusive end) of each partition
+    let mut partitions: Vec<(String, Option<String>, Option<String>)> =
+        Vec::new();
+    let mut objects: Vec<Object> = Vec::new();
+    if first.next.is_none() && first.prefixes.len() != 1 {
+        objects.extend(first.objects);
+        partitions.extend(first.prefixes.into_iter().map(|p| (p, None, None)));
+    } else {
+        // one directory, or too much to enumerate: split its key space
+        let base = if first.next.is_none() {
+            objects.extend(first.objects);
+            first.prefixes[0].clone()
+        } else {
+            prefix.to_string()
+        };
+        let n = opts.parallelism.max(2) as u32;
+        // even ranges of the byte following the base
+        let mut bounds: Vec<char> =
+            (1..n).map(|k| utf8_bound((k * 256 / n) as u8)).collect();
+        bounds.dedup();
+        let bounds: Vec<Option<String>> = bounds
+            .into_iter()
+            .map(|c| Some(format!("{}{}", base, c)))
             .collect();
-        res
+        let starts = std::iter::once(None).chain(bounds.iter().cloned());
+        let ends = bounds.iter().cloned().chain(std::iter::once(None));
+        partitions.extend(starts.zip(ends).map(|(s, e)| (base.clone(), s, e)));
+    }
+    // owned partitions, moved into each listing's future
+    let listings = futures::stream::iter(partitions.into_iter().map(
+        |(p, start, end)| async move {
+            let opts = ListOpts { start_after: start.as_deref(), ..*opts };
+            s3_list_range(s3, config, bucket, &p, &opts, end.as_deref()).await
+        },
+    ))
+    .buffered(opts.parallelism.max(1))
+    .collect::<Vec<_>>()
+    .await;
+    for listing in listings {
+        objects.extend(listing?);
+    }
+    objects.sort_by(|a, b| a.key().cmp(&b.key()));
+    Ok(objects)
+}
+
+fn s3_file_info(bucket: &str, ob: &Object) -> HashMap<String, String> {
+    let mut h: HashMap<String, String> = HashMap::new();
+    h.insert(
+        "name".to_string(),
+        format!("{}/{}", bucket, ob.key().unwrap_or("")),
+    );
+    h.insert("size".to_string(), ob.size().to_string());
+    h.insert("type".to_string(), "file".to_string());
+    if let Some(c) = ob.storage_class() {
+        h.insert("StorageClass".to_string(), c.as_str().to_string());
+    }
+    h
+}
+
+fn s3_dir_info(bucket: &str, prefix: &str) -> HashMap<String, String> {
+    let mut h: HashMap<String, String> = HashMap::new();
+    h.insert(
+        "name".to_string(),
+        format!("{}/{}", bucket, prefix.trim_end_matches('/')),
+    );
+    h.insert("size".to_string(), "0".to_string());
+    h.insert("type".to_string(), "directory".to_string());
+    h
+}
+
+/// Files (and, if withdirs, directories) below prefix, which is empty or
+/// ends with "/". Without maxdepth, the prefix is listed flat and
+/// directories are inferred from the keys; with it, each level is listed
+/// with the "/" delimiter, only as deep as needed.
+async fn s3_walk(
+    s3: &Client, config: &S3Config, bucket: &str, prefix: &str,
+    maxdepth: Option<usize>, withdirs: bool, opts: &ListOpts<'_>,
+) -> PyResult<Vec<HashMap<String, String>>> {
+    let mut output: Vec<HashMap<String, String>> = Vec::new();
+    // directories sort before their contents, so are compared by name
+    let after_start = |dir: &str| opts.start_after.is_none_or(|a| dir > a);
+    let Some(maxdepth) = maxdepth else {
+        let objects = if opts.parallelism > 1 && opts.start_after.is_none() {
+            s3_list_parallel(s3, config, bucket, prefix, opts).await?
+        } else {
+            s3_list_all(s3, config, bucket, prefix, opts).await?.objects
+        };
+        let mut dirs: HashSet<&str> = HashSet::new();
+        for ob in objects.iter() {
+            let key = ob.key().unwrap_or("");
+            if withdirs {
+                // every parent between the prefix and the key
+                let rel = &key[prefix.len()..];
+                for (i, _) in rel.match_indices('/') {
+                    let dir = &key[..prefix.len() + i];
+                    if dirs.insert(dir) && after_start(dir) {
+                        output.push(s3_dir_info(bucket, dir));
+                    }
+                }
+            }
+            if !key.ends_with('/') {
+                // not a directory marker
+                output.push(s3_file_info(bucket, ob));
+            }
+        }
+        return Ok(output);
+    };
+    let opts = ListOpts { delimiter: Some("/"), ..*opts };
+    let mut level = vec![prefix.to_string()];
+    for depth in 1..=maxdepth {
+        let pages = join_all(
+            level.iter().map(|p| s3_list_all(s3, config, bucket, p, &opts)),
+        )
+        .await;
+        let mut next: Vec<String> = Vec::new();
+        for (p, page) in level.iter().zip(pages) {
+            let page = page?;
+            for ob in page.objects.iter() {
+                if ob.key() != Some(p.as_str()) {
+                    output.push(s3_file_info(bucket, ob));
+                }
+            }
+            for sub in page.prefixes {
+                if withdirs && after_start(sub.trim_end_matches('/')) {
+                    output.push(s3_dir_info(bucket, &sub));
+                }
+                // skip prefixes sorting wholly before start_after
+                let later = match opts.start_after {
+                    Some(after) => {
+                        sub.as_str() > after || after.starts_with(&sub)
+                    }
+                    None => true,
+                };
+                if depth < maxdepth && later {
+                    next.push(sub);
+                }
+            }
+        }
+        level = next;
+        if level.is_empty() {
+            break;
+        }
+    }
+    Ok(output)
+}
+
+/// gets all keys and sizes below some root key prefix, sorted by name
+///
+/// maxdepth: levels below the root to descend, by delimited listing
+/// withdirs: include directories, as well as files
+/// start_after: path (as returned) after which to resume a listing
+/// parallelism: concurrent listings to split a large flat listing into
+#[pyfunction]
+#[pyo3(signature = (path, maxdepth=None, withdirs=false, start_after=None, parallelism=1, anon=false, requester_pays=false, **config))]
+#[allow(clippy::too_many_arguments)]
+fn s3_find<'py>(
+    py: Python<'py>, path: &str, maxdepth: Option<usize>, withdirs: bool,
+    start_after: Option<&str>, parallelism: usize, anon: bool,
+    requester_pays: bool, config: Option<&PyDict>,
+) -> PyResult<&'py PyTuple> {
+    let config = S3Config::from_kwargs(config)?;
+    let loc = s3_path(path)?;
+    let (bucket, key) = loc.parts();
+    let key = key.trim_end_matches('/');
+    let prefix = match key {
+        "" => String::new(),
+        k => format!("{}/", k),
+    };
+    let opts = ListOpts {
+        start_after: start_after.map(|s| {
+            s.strip_prefix(bucket)
+                .and_then(|s| s.strip_prefix('/'))
+                .unwrap_or(s)
+        }),
+        requester_pays,
+        anon,
+        parallelism,
+        ..Default::default()
+    };
+    let coroutine = async {
+        let s3_client = s3_bucket(&config, bucket, anon).await;
+        let mut output = s3_walk(
+            &s3_client, &config, bucket, &prefix, maxdepth, withdirs, &opts,
+        )
+        .await?;
+        if output.is_empty() && !key.is_empty() {
+            // maybe the path is itself a file
+            let opts = ListOpts { max_keys: Some(1), ..opts };
+            let page =
+                s3_list_page(&s3_client, &config, bucket, key, &opts, None)
+                    .await?;
+            if let Some(ob) = page.objects.first() {
+                if ob.key() == Some(key) {
+                    output.push(s3_file_info(bucket, ob));
+                }
+            }
+        }
+        output.sort_by(|a, b| a["name"].cmp(&b["name"]));
+        Ok::<_, PyErr>(output)
+    };
+    let output = py.allow_threads(|| RUNTIME.block_on(coroutine))?;
+    Ok(PyTuple::new(py, output.iter().map(|r| r.to_object(py))))
+}
+
+/// total size of the keys below some root key prefix or, if not total, a
+/// dict of the total in each directory, the root included
+///
+/// maxdepth: levels below the root to count files from
+/// parallelism: concurrent listings to split the flat listing into
+#[pyfunction]
+#[pyo3(signature = (path, total=true, maxdepth=None, parallelism=1, anon=false, requester_pays=false, **config))]
+#[allow(clippy::too_many_arguments)]
+fn s3_du(
+    py: Python, path: &str, total: bool, maxdepth: Option<usize>,
+    parallelism: usize, anon: bool, requester_pays: bool,
+    config: Option<&PyDict>,
+) -> PyResult<PyObject> {
+    let config = S3Config::from_kwargs(config)?;
+    let loc = s3_path(path)?;
+    let (bucket, key) = loc.parts();
+    let prefix = match key.trim_end_matches('/') {
+        "" => String::new(),
+        k => format!("{}/", k),
+    };
+    let opts =
+        ListOpts { requester_pays, anon, parallelism, ..Default::default() };
+    let coroutine = async {
+        let s3_client = s3_bucket(&config, bucket, anon).await;
+        let objects = if parallelism > 1 {
+            s3_list_parallel(&s3_client, &config, bucket, &prefix, &opts)
+                .await?
+        } else {
+            s3_list_all(&s3_client, &config, bucket, &prefix, &opts)
+                .await?
+                .objects
+        };
+        let mut usage = Usage::new(path, maxdepth, !total);
+        for ob in objects.iter() {
+            let rel = &ob.key().unwrap_or("")[prefix.len()..];
+            usage.add(rel, ob.size() as u64);
+        }
+        // nothing below the path: it may be a key itself
+        if objects.is_empty() && !prefix.is_empty() {
+            let key = key.trim_end_matches('/');
+            let enc = S3Encryption::default();
+            let head = s3_head(
+                &s3_client,
+                bucket,
+                key,
+                None,
+                requester_pays,
+                &enc,
+                anon,
+            );
+            match head.await {
+                Ok(info) => usage.add_root(info["size"].parse().unwrap_or(0)),
+                Err(e) if s3_status(&e) == Some(404) => {}
+                Err(e) => return Err(s3_err(path, e)),
+            }
+        }
+        Ok::<_, PyErr>(usage)
+    };
+    Ok(py.allow_threads(|| RUNTIME.block_on(coroutine))?.into_py(py))
+}
+
+/// gets the keys and sizes, and directory-like prefixes, directly below
+/// some root key prefix
+#[pyfunction]
+#[pyo3(signature = (path, anon=false, requester_pays=false, **config))]
+fn s3_ls<'py>(
+    py: Python<'py>, path: &str, anon: bool, requester_pays: bool,
+    config: Option<&PyDict>,
+) -> PyResult<&'py PyTuple> {
+    let config = S3Config::from_kwargs(config)?;
+    let loc = s3_path(path)?;
+    let (bucket, key) = loc.parts();
+    let prefix = match key.trim_end_matches('/') {
+        "" => String::new(),
+        k => format!("{}/", k),
+    };
+    let opts = ListOpts {
+        delimiter: Some("/"),
+        requester_pays,
+        anon,
+        ..Default::default()
+    };
+    let coroutine = async {
+        let s3_client = s3_bucket(&config, bucket, anon).await;
+        let page =
+            s3_list_all(&s3_client, &config, bucket, &prefix, &opts).await?;
+        let mut output: Vec<HashMap<String, String>> = page
+            .objects
+            .iter()
+            .filter(|ob| ob.key() != Some(prefix.as_str()))
+            .map(|ob| s3_file_info(bucket, ob))
+            .collect();
+        output.extend(page.prefixes.iter().map(|p| s3_dir_info(bucket, p)));
+        Ok::<_, PyErr>(output)
+    };
+    let output = py.allow_threads(|| RUNTIME.block_on(coroutine))?;
+    Ok(PyTuple::new(py, output.iter().map(|r| r.to_object(py))))
+}
+
+/// as s3_err, but with a conflict (the bucket already exists, or is not
+/// empty) as FileExistsError, as for directories
+fn s3_bucket_err<E>(bucket: &str, err: SdkError<E>) -> PyErr
+where
+    E: std::error::Error + 'static,
+{
+    match s3_status(&err) {
+        Some(409) => PyFileExistsError::new_err(format!(
+            "{}: {}",
+            bucket,
+            DisplayErrorContext(&err)
+        )),
+        _ => s3_err(bucket, err),
+    }
+}
+
+/// every bucket owned by the caller, as directories
+#[pyfunction]
+#[pyo3(signature = (**config))]
+fn s3_list_buckets(
+    py: Python, config: Option<&PyDict>,
+) -> PyResult<Vec<HashMap<String, String>>> {
+    let config = S3Config::from_kwargs(config)?;
+    let coroutine = async {
+        s3(&config)
+            .await
+            .list_buckets()
+            .send()
+            .await
+            .map_err(|e| s3_err("", e))
+    };
+    let resp = py.allow_threads(|| RUNTIME.block_on(coroutine))?;
+    Ok(resp
+        .buckets()
+        .unwrap_or_default()
+        .iter()
+        .map(|b| {
+            let mut h = s3_dir_info(b.name().unwrap_or(""), "");
+            h.insert("name".to_string(), b.name().unwrap_or("").into());
+            h.insert("CreationDate".to_string(), s3_time(b.creation_date()));
+            h
+        })
+        .collect())
+}
+
+/// create a bucket in the given region, else that of the client
+#[pyfunction]
+#[pyo3(signature = (bucket, region=None, acl=None, **config))]
+fn s3_create_bucket(
+    py: Python, bucket: &str, region: Option<String>, acl: Option<&str>,
+    config: Option<&PyDict>,
+) -> PyResult<()> {
+    let config = S3Config::from_kwargs(config)?;
+    let region = region.or_else(|| config.region.clone());
+    // us-east-1 is the default location, and may not be given as one
+    let location = region.as_deref().filter(|r| *r != "us-east-1").map(|r| {
+        CreateBucketConfiguration::builder()
+            .location_constraint(BucketLocationConstraint::from(r))
+            .build()
+    });
+    let coroutine = async {
+        // sent to the new bucket's region
+        let s3_client =
+            s3(&S3Config { region: region.clone(), ..config.clone() }).await;
+        s3_client
+            .create_bucket()
+            .bucket(bucket)
+            .set_create_bucket_configuration(location)
+            .set_acl(acl.map(BucketCannedAcl::from))
+            .send()
+            .await
+            .map_err(|e| s3_bucket_err(bucket, e))
     };
+    py.allow_threads(|| RUNTIME.block_on(coroutine))?;
+    if let (Some(r), None) = (region, &config.endpoint_url) {
+        let known = Arc::new(OnceCell::from(Some(r)));
+        S3_REGIONS.lock().unwrap().insert(bucket.into(), known);
+    }
+    Ok(())
+}
 
-    py.allow_threads(|| RUNTIME.block_on(coroutine))
+/// delete a bucket, which must be empty
+#[pyfunction]
+#[pyo3(signature = (bucket, **config))]
+fn s3_delete_bucket(
+    py: Python, bucket: &str, config: Option<&PyDict>,
+) -> PyResult<()> {
+    let config = S3Config::from_kwargs(config)?;
+    let coroutine = async {
+        let s3_client = s3_bucket(&config, bucket, false).await;
+        s3_client
+            .delete_bucket()
+            .bucket(bucket)
+            .send()
+            .await
+            .map_err(|e| s3_bucket_err(bucket, e))
+    };
+    py.allow_threads(|| RUNTIME.block_on(coroutine))?;
+    s3_forget_region(bucket);
+    Ok(())
 }
 
-async fn s3_get_one_range(
-    url: &str, s3: Client, start: i64, end: i64, requester_pays: bool,
-    anon: bool,
-) -> Vec<u8> {
-    let out = url.split_once("/");
-    match out {
-        None => b"S3 ERROR: bad path".to_vec(),
-        Some((bucket, key)) => {
-            let mut resp = s3.get_object().bucket(bucket).key(key);
-            println!("{}", url);
-            if (start != 0) | (end != 0) {
-                let range = match end {
-                    0 => format!("bytes={}", start), // None/not set
-                    -1 => format!("bytes={}", start), // to the end
-                    _ => format!("bytes={}-{}", start, end),
-                };
-                println!("{}", range);
-                resp = resp.set_range(Some(range))
+/// files and directories matching a glob pattern, "bucket/key-pattern",
+/// sorted by name, and with maxdepth, at most that many levels below the
+/// pattern's literal directory
+#[pyfunction]
+#[pyo3(signature = (path, maxdepth=None, anon=false, requester_pays=false, **config))]
+fn s3_glob<'py>(
+    py: Python<'py>, path: &str, maxdepth: Option<usize>, anon: bool,
+    requester_pays: bool, config: Option<&PyDict>,
+) -> PyResult<&'py PyTuple> {
+    let config = S3Config::from_kwargs(config)?;
+    let loc = s3_path(path)?;
+    let (bucket, key) = loc.parts();
+    let pattern = Pattern::new(key);
+    let coroutine = async {
+        let s3_client = s3_bucket(&config, bucket, anon).await;
+        let (client, config) = (&s3_client, &config);
+        glob(bucket, &pattern, maxdepth, |prefix, delimited| async move {
+            let opts = ListOpts {
+                delimiter: delimited.then_some("/"),
+                requester_pays,
+                anon,
+                ..Default::default()
             };
-            if requester_pays {
-                resp = resp.set_request_payer(Some(RequestPayer::Requester));
+            let page =
+                s3_list_all(client, config, bucket, &prefix, &opts).await?;
+            let files = page.objects.iter().map(|ob| s3_file_info(bucket, ob));
+            Ok::<Listing, PyErr>((files.collect(), page.prefixes))
+        })
+        .await
+    };
+    let output = py.allow_threads(|| RUNTIME.block_on(coroutine))?;
+    Ok(PyTuple::new(py, output.iter().map(|r| r.to_object(py))))
+}
+
+/// size, ETag and so on of a key, by HEAD
+async fn s3_head(
+    s3_client: &Client, bucket: &str, key: &str, version_id: Option<&str>,
+    requester_pays: bool, enc: &S3Encryption, anon: bool,
+) -> Result<HashMap<String, String>, SdkError<HeadObjectError>> {
+    let mut req = s3_client
+        .head_object()
+        .bucket(bucket)
+        .key(key)
+        .set_version_id(version_id.map(|v| v.to_string()));
+    if requester_pays {
+        req = req.request_payer(RequestPayer::Requester);
+    }
+    req = encrypt!(req, enc, customer);
+    let r = send!(req, anon)?;
+    let mut output: HashMap<String, String> = HashMap::new();
+    output.insert("size".to_string(), format!("{}", r.content_length()));
+    output.insert("type".to_string(), "file".to_string());
+    output.insert("ETag".to_string(), r.e_tag().unwrap_or("").to_string());
+    output.insert("LastModified".to_string(), s3_time(r.last_modified()));
+    if let Some(v) = r.version_id() {
+        output.insert("VersionId".to_string(), v.to_string());
+    }
+    if let Some(v) = r.server_side_encryption() {
+        output.insert(
+            "ServerSideEncryption".to_string(),
+            v.as_str().to_string(),
+        );
+    }
+    if let Some(v) = r.ssekms_key_id() {
+        output.insert("SSEKMSKeyId".to_string(), v.to_string());
+    }
+    // not given for STANDARD
+    let class = r.storage_class().map_or("STANDARD", |c| c.as_str());
+    output.insert("StorageClass".to_string(), class.to_string());
+    if let Some(v) = r.archive_status() {
+        output.insert("ArchiveStatus".to_string(), v.as_str().to_string());
+    }
+    if let Some(v) = r.object_lock_mode() {
+        output.insert("ObjectLockMode".to_string(), v.as_str().to_string());
+    }
+    if r.object_lock_retain_until_date().is_some() {
+        output.insert(
+            "ObjectLockRetainUntilDate".to_string(),
+            s3_time(r.object_lock_retain_until_date()),
+        );
+    }
+    if let Some(v) = r.object_lock_legal_hold_status() {
+        output.insert(
+            "ObjectLockLegalHoldStatus".to_string(),
+            v.as_str().to_string(),
+        );
+    }
+    if let Some(restore) = r.restore() {
+        let (ongoing, expiry) = s3_restore_status(restore);
+        output.insert("RestoreInProgress".to_string(), ongoing.to_string());
+        if let Some(d) = expiry {
+            output.insert("RestoreExpiryDate".to_string(), d.to_string());
+        }
+    }
+    Ok(output)
+}
+
+/// whether a restore is in progress, and when the restored copy expires,
+/// from an x-amz-restore header such as
+/// `ongoing-request="false", expiry-date="Fri, 21 Dec 2012 00:00:00 GMT"`
+fn s3_restore_status(header: &str) -> (bool, Option<&str>) {
+    let ongoing = header.contains("ongoing-request=\"true\"");
+    let expiry = header
+        .split_once("expiry-date=\"")
+        .and_then(|(_, rest)| rest.split_once('"'))
+        .map(|(date, _)| date);
+    (ongoing, expiry)
+}
+
+/// request restores of archived objects, each readable for `days` once
+/// restored, from the "Expedited", "Standard" or "Bulk" retrieval tier.
+/// Objects already being restored, or not archived, are left alone; the
+/// progress of each is in s3_info's RestoreInProgress
+#[pyfunction]
+#[pyo3(signature = (paths, days=1, tier="Standard", version_id=None, concurrency=32, requester_pays=false, anon=false, **config))]
+#[allow(clippy::too_many_arguments)]
+fn s3_restore(
+    py: Python, paths: Vec<&str>, days: i32, tier: &str,
+    version_id: Option<Vec<Option<String>>>, concurrency: usize,
+    requester_pays: bool, anon: bool, config: Option<&PyDict>,
+) -> PyResult<()> {
+    let config = S3Config::from_kwargs(config)?;
+    let version_id = version_id.unwrap_or_else(|| vec![None; paths.len()]);
+    let request = RestoreRequest::builder()
+        .days(days)
+        .glacier_job_parameters(
+            GlacierJobParameters::builder().tier(Tier::from(tier)).build(),
+        )
+        .build();
+    let paths: Vec<String> = paths.into_iter().map(String::from).collect();
+    let coroutine = async {
+        let (config, request) = (&config, &request);
+        futures::stream::iter(paths.into_iter().zip(version_id))
+            .map(|(path, v)| async move {
+                let path: &str = &path;
+                let loc = s3_key_path(path)?;
+                let (bucket, key) = loc.parts();
+                let s3_client = s3_bucket(config, bucket, anon).await;
+                let req = s3_client
+                    .restore_object()
+                    .bucket(bucket)
+                    .key(key)
+                    .set_version_id(v)
+                    .restore_request(request.clone())
+                    .set_request_payer(
+                        requester_pays.then_some(RequestPayer::Requester),
+                    );
+                match send!(req, anon) {
+                    Ok(_) => Ok(()),
+                    Err(e) => {
+                        let nothing_to_do =
+                            s3_service_err(&e).is_some_and(|se| {
+                                se.is_object_already_in_active_tier_error()
+                                    || se.code()
+                                        == Some("RestoreAlreadyInProgress")
+                            });
+                        match nothing_to_do {
+                            true => Ok(()),
+                            false => Err(s3_err(path, e)),
+                        }
+                    }
+                }
+            })
+            .buffered(concurrency.max(1))
+            .collect::<Vec<PyResult<()>>>()
+            .await
+            .into_iter()
+            .collect::<PyResult<Vec<()>>>()
+    };
+    py.allow_threads(|| RUNTIME.block_on(coroutine))?;
+    Ok(())
+}
+
+/// gets the size of a single key, or identifies a directory-like prefix
+#[pyfunction]
+#[pyo3(signature = (path, anon=false, requester_pays=false, version_id=None, sse_customer_key=None, **config))]
+fn s3_info(
+    py: Python, path: &str, anon: bool, requester_pays: bool,
+    version_id: Option<&str>, sse_customer_key: Option<&str>,
+    config: Option<&PyDict>,
+) -> PyResult<HashMap<String, String>> {
+    let config = S3Config::from_kwargs(config)?;
+    let enc = S3Encryption::new(None, None, sse_customer_key)?;
+    let loc = s3_path(path)?;
+    let (bucket, key) = loc.parts();
+    let coroutine = async {
+        let s3_client = s3_bucket(&config, bucket, anon).await;
+        let mut output: HashMap<String, String> = HashMap::new();
+        let head = if key.is_empty() {
+            // bucket root is never a key
+            None
+        } else {
+            let head = s3_head(
+                &s3_client,
+                bucket,
+                key,
+                version_id,
+                requester_pays,
+                &enc,
+                anon,
+            );
+            match head.await {
+                Ok(info) => return Ok(info),
+                Err(e) if s3_status(&e) == Some(404) => Some(e),
+                Err(e) => return Err(s3_err(path, e)),
             }
-            let resp = if anon {
-                resp.customize()
-                    .await
-                    .unwrap()
-                    .map_operation(make_unsigned)
-                    .unwrap()
-                    .send()
-                    .await
-            } else {
-                resp.send().await
-            };
-            match resp {
-                // Convert the body into a bytes vec
-                Ok(r) => r.body.collect().await.unwrap().into_bytes().to_vec(),
-                Err(SdkError::ResponseError(e)) => {
-                    [b"S3 ERRROR: ", e.raw().http().body().bytes().unwrap()]
-                        .concat()
+        };
+
+        // not a key: is there anything below it?
+        let prefix = match key.trim_end_matches("/") {
+            "" => String::new(),
+            k => format!("{}/", k),
+        };
+        let opts = ListOpts {
+            delimiter: Some("/"),
+            max_keys: Some(1),
+            requester_pays,
+            anon,
+            ..Default::default()
+        };
+        let resp =
+            s3_list_page(&s3_client, &config, bucket, &prefix, &opts, None)
+                .await;
+        match (resp, head) {
+            (Ok(_), Some(e)) if version_id.is_some() => Err(s3_err(path, e)),
+            (Ok(page), _)
+                if key.is_empty()
+                    || !page.objects.is_empty()
+                    || !page.prefixes.is_empty() =>
+            {
+                output.insert("size".to_string(), "0".to_string());
+                output.insert("type".to_string(), "directory".to_string());
+                Ok(output)
+            }
+            (Ok(_), _) => Err(PyFileNotFoundError::new_err(path.to_string())),
+            (Err(_), Some(e)) => Err(s3_err(path, e)),
+            (Err(e), None) => Err(e),
+        }
+    };
+    py.allow_threads(|| RUNTIME.block_on(coroutine))
+}
+
+/// info of many keys, by HEAD requests with up to `concurrency` at once:
+/// a list in the order of paths, with None for any that is not a key
+#[pyfunction]
+#[pyo3(signature = (paths, concurrency=32, anon=false, requester_pays=false, version_id=None, sse_customer_key=None, **config))]
+#[allow(clippy::too_many_arguments)]
+fn s3_info_many(
+    py: Python, paths: Vec<&str>, concurrency: usize, anon: bool,
+    requester_pays: bool, version_id: Option<Vec<Option<String>>>,
+    sse_customer_key: Option<&str>, config: Option<&PyDict>,
+) -> PyResult<Vec<Option<HashMap<String, String>>>> {
+    let config = S3Config::from_kwargs(config)?;
+    let enc = S3Encryption::new(None, None, sse_customer_key)?;
+    let version_id = version_id.unwrap_or_else(|| vec![None; paths.len()]);
+    let paths: Vec<String> = paths.into_iter().map(String::from).collect();
+    let coroutine = async {
+        let (config, enc) = (&config, &enc);
+        futures::stream::iter(paths.into_iter().zip(version_id))
+            .map(|(path, v)| async move {
+                let path: &str = &path;
+                let loc = s3_path(path)?;
+                let (bucket, key) = loc.parts();
+                if key.is_empty() {
+                    return Ok(None);
+                }
+                let s3_client = s3_bucket(config, bucket, anon).await;
+                let head = s3_head(
+                    &s3_client,
+                    bucket,
+                    key,
+                    v.as_deref(),
+                    requester_pays,
+                    enc,
+                    anon,
+                );
+                match head.await {
+                    Ok(info) => Ok(Some(info)),
+                    Err(e) if s3_status(&e) == Some(404) => Ok(None),
+                    Err(e) => Err(s3_err(path, e)),
                 }
-                Err(SdkError::ServiceError(e)) => {
-                    [b"S3 ERRROR: ", e.raw().http().body().bytes().unwrap()]
-                        .concat()
-                        .to_vec()
+            })
+            .buffered(concurrency.max(1))
+            .collect::<Vec<PyResult<_>>>()
+            .await
+            .into_iter()
+            .collect()
+    };
+    py.allow_threads(|| RUNTIME.block_on(coroutine))
+}
+
+/// version_id: optional list, one per path, of VersionId or None for latest
+/// if_match: optional list, one per path, of the ETag the object must have
+/// if_unmodified_since: optional list, one per path, of epoch seconds
+///
+/// Raises FileChangedError if any object fails its condition
+///
+/// verify: check whole objects (start and end 0) against their stored
+/// checksums, raising ChecksumError
+#[pyfunction]
+#[pyo3(signature = (path, start, end, anon=false, requester_pays=false, version_id=None, sse_customer_key=None, if_match=None, if_unmodified_since=None, verify=false, **config))]
+#[allow(clippy::too_many_arguments)]
+fn s3_cat_ranges<'py>(
+    py: Python<'py>, path: Vec<&str>, start: Vec<i64>, end: Vec<i64>,
+    anon: bool, requester_pays: bool, version_id: Option<Vec<Option<String>>>,
+    sse_customer_key: Option<&str>, if_match: Option<Vec<Option<String>>>,
+    if_unmodified_since: Option<Vec<Option<f64>>>, verify: bool,
+    config: Option<&PyDict>,
+) -> PyResult<&'py PyTuple> {
+    let config = S3Config::from_kwargs(config)?;
+    let n = path.len();
+    let versions = version_id.unwrap_or_else(|| vec![None; n]);
+    let etags = if_match.unwrap_or_else(|| vec![None; n]);
+    let since = if_unmodified_since.unwrap_or_else(|| vec![None; n]);
+    let pins =
+        versions.into_iter().zip(etags).zip(since).map(|((v, m), t)| {
+            S3ReadPin { version_id: v, if_match: m, if_unmodified_since: t }
+        });
+    let enc = S3Encryption::new(None, None, sse_customer_key)?;
+    let coroutine = async {
+        join_all(path.iter().zip(start).zip(end).zip(pins).map(
+            |(((u, st), e), pin)| {
+                s3_get_one_range(
+                    u,
+                    &config,
+                    st,
+                    e,
+                    requester_pays,
+                    anon,
+                    pin,
+                    &enc,
+                    verify,
+                )
+            },
+        ))
+        .await
+        .into_iter()
+        .collect::<PyResult<Vec<Vec<u8>>>>()
+    };
+    let result = py.allow_threads(|| RUNTIME.block_on(coroutine))?;
+    Ok(PyTuple::new(py, result.iter().map(|r| PyBytes::new(py, &r[..]))))
+}
+
+use aws_sdk_s3::types::error::SelectObjectContentEventStreamError;
+use aws_sdk_s3::types::{
+    CompressionType, CsvInput, CsvOutput, ExpressionType, FileHeaderInfo,
+    InputSerialization, JsonInput, JsonOutput, JsonType, OutputSerialization,
+    ParquetInput, SelectObjectContentEventStream,
+};
+use aws_smithy_http::event_stream::Receiver;
+
+type SelectStream = Receiver<
+    SelectObjectContentEventStream,
+    SelectObjectContentEventStreamError,
+>;
+
+/// How S3 Select should parse an object and write out matching records
+///
+/// input_format: "csv", "json" (one document per line), "json-document" or
+/// "parquet"; output_format: "csv" or "json"
+fn select_serialization(
+    input_format: &str, output_format: &str, compression: Option<&str>,
+    csv_header: &str, delimiter: &str,
+) -> PyResult<(InputSerialization, OutputSerialization)> {
+    let input = InputSerialization::builder()
+        .set_compression_type(compression.map(CompressionType::from));
+    let input =
+        match input_format.to_lowercase().as_str() {
+            "csv" => input.csv(
+                CsvInput::builder()
+                    .file_header_info(FileHeaderInfo::from(
+                        csv_header.to_uppercase().as_str(),
+                    ))
+                    .field_delimiter(delimiter)
+                    .build(),
+            ),
+            "json" => input
+                .json(JsonInput::builder().r#type(JsonType::Lines).build()),
+            "json-document" => input
+                .json(JsonInput::builder().r#type(JsonType::Document).build()),
+            "parquet" => input.parquet(ParquetInput::builder().build()),
+            other => {
+                return Err(PyValueError::new_err(format!(
+                    "unknown input format {}",
+                    other
+                )))
+            }
+        };
+    let output = match output_format.to_lowercase().as_str() {
+        "csv" => OutputSerialization::builder()
+            .csv(CsvOutput::builder().field_delimiter(delimiter).build()),
+        "json" => {
+            OutputSerialization::builder().json(JsonOutput::builder().build())
+        }
+        other => {
+            return Err(PyValueError::new_err(format!(
+                "unknown output format {}",
+                other
+            )))
+        }
+    };
+    Ok((input.build(), output.build()))
+}
+
+/// Add the requester-pays header, which the SDK's builder lacks for selects,
+/// then remove signing if anon
+fn select_headers<O, Retry>(
+    mut operation: aws_smithy_http::operation::Operation<O, Retry>,
+    requester_pays: bool, anon: bool,
+) -> Result<
+    aws_smithy_http::operation::Operation<O, Retry>,
+    std::convert::Infallible,
+> {
+    if requester_pays {
+        let headers = operation.request_mut().headers_mut();
+        headers.insert("x-amz-request-payer", "requester".parse().unwrap());
+    }
+    if anon {
+        return make_unsigned(operation);
+    }
+    Ok(operation)
+}
+
+/// Start a select, giving its stream of events
+#[allow(clippy::too_many_arguments)]
+async fn s3_select_start(
+    path: &str, expression: &str, input: InputSerialization,
+    output: OutputSerialization, config: &S3Config, requester_pays: bool,
+    anon: bool, enc: &S3Encryption,
+) -> PyResult<SelectStream> {
+    let loc = s3_key_path(path)?;
+    let (bucket, key) = loc.parts();
+    let s3 = s3_bucket(config, bucket, anon).await;
+    let req = s3
+        .select_object_content()
+        .bucket(bucket)
+        .key(key)
+        .expression(expression)
+        .expression_type(ExpressionType::Sql)
+        .input_serialization(input)
+        .output_serialization(output);
+    let req = encrypt!(req, enc, customer);
+    let resp = send!(@map req, |op| select_headers(op, requester_pays, anon))
+        .map_err(|e| s3_err(path, e))?;
+    Ok(resp.payload)
+}
+
+/// The next block of records from a select, or None once it has ended
+async fn select_next(
+    path: &str, payload: &mut SelectStream,
+) -> PyResult<Option<Vec<u8>>> {
+    loop {
+        let event = payload.recv().await.map_err(|e| {
+            PyRuntimeError::new_err(format!(
+                "S3 ERROR: {}: {}",
+                path,
+                DisplayErrorContext(&e)
+            ))
+        })?;
+        match event {
+            Some(SelectObjectContentEventStream::Records(records)) => {
+                if let Some(data) = records.payload() {
+                    return Ok(Some(data.as_ref().to_vec()));
                 }
-                Err(e) => format!("S3 ERRROR: {}", e).as_bytes().to_vec(),
             }
+            Some(SelectObjectContentEventStream::End(_)) => return Ok(None),
+            // only End says that all the records were sent
+            None => {
+                return Err(PyIOError::new_err(format!(
+                    "S3 ERROR: {}: select ended without its End event; \
+                     records are incomplete",
+                    path
+                )))
+            }
+            // stats, progress and keep-alive messages
+            Some(_) => {}
+        }
+    }
+}
+
+/// Records from a select, a block of bytes at a time as they arrive
+#[pyclass]
+struct SelectRecords {
+    path: String,
+    payload: SelectStream,
+    done: bool,
+}
+
+#[pymethods]
+impl SelectRecords {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    /// next block, waiting for it with the GIL released
+    fn __next__(
+        mut slf: PyRefMut<'_, Self>, py: Python,
+    ) -> PyResult<Option<PyObject>> {
+        if slf.done {
+            return Ok(None);
         }
+        let records: &mut SelectRecords = &mut slf;
+        let block = py.allow_threads(|| {
+            RUNTIME.block_on(select_next(&records.path, &mut records.payload))
+        })?;
+        records.done = block.is_none();
+        Ok(block.map(|b| PyBytes::new(py, &b).into()))
     }
 }
 
-/// gets all keys and sizes below some root key prefix
+/// run an SQL expression against the object at "bucket/key" with S3 Select,
+/// giving the matching records as bytes, or as an iterator of blocks of bytes
+/// with `iterator`
+///
+/// compression: "GZIP" or "BZIP2" for compressed CSV or JSON
+/// csv_header: "USE", "IGNORE" or "NONE" for whether the first line names
+/// the columns
 #[pyfunction]
-#[pyo3(signature = (path, region=None, profile=None, endpoint_url=None, anon=false, requester_pays=false))]
-fn s3_find<'py>(
-    py: Python<'py>, path: &str, region: Option<&str>, profile: Option<&str>,
-    endpoint_url: Option<&str>, anon: bool, requester_pays: bool,
-) -> &'py PyTuple {
-    let mut output: Vec<HashMap<String, String>> = Vec::new();
+#[pyo3(signature = (path, expression, input_format="csv", output_format="json", compression=None, csv_header="USE", delimiter=",", iterator=false, anon=false, requester_pays=false, sse_customer_key=None, **config))]
+#[allow(clippy::too_many_arguments)]
+fn s3_select(
+    py: Python, path: &str, expression: &str, input_format: &str,
+    output_format: &str, compression: Option<&str>, csv_header: &str,
+    delimiter: &str, iterator: bool, anon: bool, requester_pays: bool,
+    sse_customer_key: Option<&str>, config: Option<&PyDict>,
+) -> PyResult<PyObject> {
+    let config = S3Config::from_kwargs(config)?;
+    let enc = S3Encryption::new(None, None, sse_customer_key)?;
+    let (input, output) = select_serialization(
+        input_format,
+        output_format,
+        compression,
+        csv_header,
+        delimiter,
+    )?;
     let coroutine = async {
-        let s3_client = s3(region, profile, endpoint_url).await;
-        let out = path.split_once("/");
-        if let Some((bucket, key)) = out {
-            let mut tok: Option<String> = None;
-            loop {
-                let mut resp =
-                    s3_client.list_objects_v2().bucket(bucket).prefix(key);
-                if requester_pays {
-                    resp = resp.request_payer(RequestPayer::Requester);
-                };
-                if let Some(tt) = tok {
-                    resp = resp.continuation_token(tt);
-                }
-                let resp = if anon {
-                    resp.customize()
-                        .await
-                        .unwrap()
-                        .map_operation(make_unsigned)
-                        .unwrap()
-                        .send()
-                        .await
-                } else {
-                    resp.send().await
-                };
-                if let Ok(page) = resp {
-                    tok =
-                        page.next_continuation_token().map(|t| t.to_string());
-
-                    for ob in page.contents().unwrap().iter() {
-                        let mut h: HashMap<String, String> = HashMap::new();
-                        h.insert(
-                            "name".to_string(),
-                            format!("{}/{}", bucket, ob.key().unwrap()),
-                        );
-                        h.insert("size".to_string(), ob.size().to_string());
-                        output.push(h)
-                    }
-                    if tok.is_none() {
-                        // last response
-                        break;
-                    }
-                } else {
-                    // no response
-                    break;
-                }
+        let mut payload = s3_select_start(
+            path,
+            expression,
+            input,
+            output,
+            &config,
+            requester_pays,
+            anon,
+            &enc,
+        )
+        .await?;
+        if iterator {
+            return Ok((Some(payload), Vec::new()));
+        }
+        let mut out: Vec<u8> = Vec::new();
+        while let Some(block) = select_next(path, &mut payload).await? {
+            out.extend(block);
+        }
+        Ok::<_, PyErr>((None, out))
+    };
+    match py.allow_threads(|| RUNTIME.block_on(coroutine))? {
+        (Some(payload), _) => Ok(Py::new(
+            py,
+            SelectRecords { path: path.to_string(), payload, done: false },
+        )?
+        .into_py(py)),
+        (None, out) => Ok(PyBytes::new(py, &out).into()),
+    }
+}
+
+/// The GCS endpoint: STORAGE_EMULATOR_HOST if set, as for Google's own
+/// clients, for an emulator such as fake-gcs-server
+fn gcs_host() -> String {
+    match std::env::var("STORAGE_EMULATOR_HOST") {
+        Ok(h) if h.contains("://") => h.trim_end_matches('/').to_string(),
+        Ok(h) => format!("http://{}", h.trim_end_matches('/')),
+        Err(_) => "https://storage.googleapis.com".to_string(),
+    }
+}
+
+async fn gcs() -> PyResult<TokenManager> {
+    let cname: &str = "full-control";
+    if GCS_TOKEN.lock().unwrap().contains_key(cname) {
+        // clone is free since "client" is actually an Arc pointing to real object
+        return Ok(GCS_TOKEN.lock().unwrap().get(cname).unwrap().clone());
+    }
+    let tok = TokenManager::new(&[cname]).await.map_err(gcs_auth_err)?;
+    GCS_TOKEN.lock().unwrap().insert(cname.to_string(), tok.clone());
+    Ok(tok)
+}
+
+fn gcs_auth_err(err: impl std::fmt::Display) -> PyErr {
+    PyPermissionError::new_err(format!("GCS ERROR: no credentials: {}", err))
+}
+
+/// the authorization header value for GCS requests
+async fn gcs_token() -> PyResult<String> {
+    gcs().await?.token().await.map_err(gcs_auth_err)
+}
+
+/// With a generation, a 412 response means that the object was replaced,
+/// and is raised rather than returned as error bytes
+async fn gcs_get_range(
+    path: &str, tok: Option<String>, start: usize, end: usize,
+    project: Option<&str>, requester_pays: bool, generation: Option<i64>,
+) -> PyResult<Bytes> {
+    let mut head: HashMap<&str, String> = HashMap::new();
+    let mut extra: String = String::new();
+    if let Some(tok_str) = tok {
+        head.insert("authorization", tok_str);
+        if let Some(proj) = project {
+            head.insert("x-goog-user-project", proj.to_string());
+            if requester_pays {
+                extra.extend(format!("&userProject={}", proj).chars());
             }
         };
+    }
+    if let Some(gen) = generation {
+        extra.extend(format!("&ifGenerationMatch={}", gen).chars());
+    }
+    let (bucket, key) = path.split_once("/").unwrap();
+    let host = gcs_host();
+    let url = format!(
+        "{}/download/storage/v1/b/{}/o/{}?alt=media{}",
+        host,
+        bucket,
+        encode(key),
+        extra
+    );
+    if generation.is_none() {
+        return Ok(get_url_or(
+            &url[..],
+            start,
+            end,
+            head,
+            &reqwest::Method::GET,
+        )
+        .await);
+    }
+    let mut req = CLIENT.get(&url);
+    for (key, value) in head.iter() {
+        req = req.header(*key, value);
+    }
+    if (start > 0) | (end != 0) {
+        req = req.header("Range", format!("bytes={}-{}", start, end - 1));
+    }
+    let out = match req.send().await {
+        Ok(r) if r.status().as_u16() == 412 => {
+            return Err(FileChangedError::new_err(format!(
+                "{} changed since it was opened (generation {})",
+                path,
+                generation.unwrap()
+            )))
+        }
+        Ok(r) => r.bytes().await,
+        Err(e) => Err(e),
     };
-    py.allow_threads(|| RUNTIME.block_on(coroutine));
-    PyTuple::new(py, output.iter().map(|r| r.to_object(py)))
+    Ok(out.unwrap_or_else(|e| Bytes::from(format!("HTTP ERROR: {}", e))))
 }
 
-/// gets all keys and sizes below some root key prefix
+/// generation: optional list, one per path, of the generation the object
+/// must still have; raises FileChangedError if any has been replaced
 #[pyfunction]
-#[pyo3(signature = (path, region=None, profile=None, endpoint_url=None, anon=false, requester_pays=false))]
-fn s3_ls<'py>(
-    py: Python<'py>, path: &str, region: Option<&str>, profile: Option<&str>,
-    endpoint_url: Option<&str>, anon: bool, requester_pays: bool,
-) -> &'py PyTuple {
-    let mut output: Vec<HashMap<String, String>> = Vec::new();
+#[allow(clippy::too_many_arguments)]
+fn gcs_cat_ranges<'py>(
+    py: Python<'py>, path: Vec<&str>, start: Vec<usize>, end: Vec<usize>,
+    requester_pays: bool, anon: bool, project: Option<&str>,
+    generation: Option<Vec<Option<i64>>>,
+) -> PyResult<&'py PyTuple> {
+    let generation = generation.unwrap_or_else(|| vec![None; path.len()]);
     let coroutine = async {
-        let s3_client = s3(region, profile, endpoint_url).await;
-        let out = path.split_once("/");
-        if let Some((bucket, key)) = out {
-            let mut tok: Option<String> = None;
-            let key = if !key.ends_with("/") {
-                format!("{}/", key)
-            } else {
-                key.into()
-            };
+        let tok: Option<String> = match anon {
+            true => None,
+            false => Some(gcs_token().await?),
+        };
+        join_all(path.iter().zip(start).zip(end).zip(generation).map(
+            |(((u, st), e), g)| {
+                gcs_get_range(
+                    u,
+                    tok.clone(),
+                    st,
+                    e,
+                    project,
+                    requester_pays,
+                    g,
+                )
+            },
+        ))
+        .await
+        .into_iter()
+        .collect::<PyResult<Vec<Bytes>>>()
+    };
+    let result = py.allow_threads(|| RUNTIME.block_on(coroutine))?;
+    Ok(PyTuple::new(py, result.iter().map(|r| PyBytes::new(py, &r[..]))))
+}
 
-            loop {
-                let mut resp = s3_client
-                    .list_objects_v2()
-                    .bucket(bucket)
-                    .prefix(key.clone())
-                    .delimiter("/");
-                if requester_pays {
-                    resp = resp.request_payer(RequestPayer::Requester);
-                };
-                if let Some(tt) = tok {
-                    resp = resp.continuation_token(tt);
-                }
-                let resp = if anon {
-                    resp.customize()
-                        .await
-                        .unwrap()
-                        .map_operation(make_unsigned)
-                        .unwrap()
-                        .send()
-                        .await
-                } else {
-                    resp.send().await
-                };
-                if let Ok(page) = resp {
-                    tok =
-                        page.next_continuation_token().map(|t| t.to_string());
-
-                    let cont = page.contents();
-                    if cont.is_some() {
-                        for ob in page.contents().unwrap().iter() {
-                            let mut h: HashMap<String, String> =
-                                HashMap::new();
-                            h.insert(
-                                "name".to_string(),
-                                format!("{}/{}", bucket, ob.key().unwrap()),
-                            );
-                            h.insert(
-                                "size".to_string(),
-                                ob.size().to_string(),
-                            );
-                            output.push(h)
-                        }
-                    }
-                    let cont = page.common_prefixes();
-                    if cont.is_some() {
-                        for ob in page.common_prefixes().unwrap().iter() {
-                            let mut h: HashMap<String, String> =
-                                HashMap::new();
-                            h.insert(
-                                "name".to_string(),
-                                format!("{}/{}", bucket, ob.prefix().unwrap()),
-                            );
-                            h.insert("size".to_string(), "0".into());
-                            h.insert("type".to_string(), "directory".into());
-                            output.push(h)
-                        }
-                    }
+fn gcs_err(path: &str, status: u16, text: &str) -> PyErr {
+    match status {
+        404 => PyFileNotFoundError::new_err(path.to_string()),
+        401 | 403 => PyPermissionError::new_err(format!("{}: {}", path, text)),
+        _ => PyRuntimeError::new_err(format!("GCS ERROR: {}: {}", path, text)),
+    }
+}
 
-                    if tok.is_none() {
-                        // last response
-                        break;
-                    }
-                } else {
-                    // no response
-                    break;
+/// upload bytes to "bucket/name", returning its generation and ETag
+///
+/// if_generation_match: write only if the object is at this generation, or
+/// with 0 only if it does not exist; else raise PreconditionFailedError
+#[pyfunction]
+#[pyo3(signature = (path, data, requester_pays=false, anon=false, project=None, if_generation_match=None))]
+fn gcs_pipe(
+    py: Python, path: &str, data: &PyAny, requester_pays: bool, anon: bool,
+    project: Option<&str>, if_generation_match: Option<i64>,
+) -> PyResult<HashMap<String, String>> {
+    let (bucket, key) = path
+        .split_once("/")
+        .ok_or_else(|| PyValueError::new_err("GCS ERROR: bad path"))?;
+    let data: &[u8] = py_to_byteslice(data);
+    let coroutine = async {
+        let mut req = CLIENT
+            .post(format!("{}/upload/storage/v1/b/{}/o", gcs_host(), bucket))
+            .query(&[("uploadType", "media"), ("name", key)])
+            .body(Bytes::from_static(data));
+        if let Some(gen) = if_generation_match {
+            req = req.query(&[("ifGenerationMatch", gen)]);
+        }
+        if !anon {
+            req = req.header("authorization", gcs_token().await?);
+            if let Some(proj) = project {
+                req = req.header("x-goog-user-project", proj);
+                if requester_pays {
+                    req = req.query(&[("userProject", proj)]);
                 }
             }
-        };
+        }
+        let resp = req.send().await.map_err(|e| {
+            PyIOError::new_err(format!("GCS ERROR: {}: {}", path, e))
+        })?;
+        let status = resp.status().as_u16();
+        let body = resp.bytes().await.map_err(|e| {
+            PyIOError::new_err(format!("GCS ERROR: {}: {}", path, e))
+        })?;
+        match status {
+            412 => Err(precondition_err(path)),
+            s if s >= 400 => {
+                Err(gcs_err(path, s, &String::from_utf8_lossy(&body)))
+            }
+            _ => serde_json::from_slice::<serde_json::Value>(&body)
+                .map_err(|e| gcs_err(path, status, &e.to_string())),
+        }
     };
-    py.allow_threads(|| RUNTIME.block_on(coroutine));
-    PyTuple::new(py, output.iter().map(|r| r.to_object(py)))
+    let out = py.allow_threads(|| RUNTIME.block_on(coroutine))?;
+    let mut h: HashMap<String, String> = HashMap::new();
+    for field in ["generation", "etag", "size"] {
+        if let Some(v) = out[field].as_str() {
+            h.insert(field.to_string(), v.to_string());
+        }
+    }
+    Ok(h)
 }
 
-/// gets the size of a single key
+/// send a JSON API request, authorized unless anon, giving the response's
+/// JSON, or Null if it has none
+async fn gcs_json(
+    path: &str, req: reqwest::RequestBuilder, anon: bool,
+    project: Option<&str>, requester_pays: bool,
+) -> PyResult<serde_json::Value> {
+    gcs_json_found(path, req, anon, project, requester_pays)
+        .await?
+        .ok_or_else(|| PyFileNotFoundError::new_err(path.to_string()))
+}
+
+/// as gcs_json, but None if there is nothing at the path
+async fn gcs_json_found(
+    path: &str, mut req: reqwest::RequestBuilder, anon: bool,
+    project: Option<&str>, requester_pays: bool,
+) -> PyResult<Option<serde_json::Value>> {
+    if !anon {
+        req = req.header("authorization", gcs_token().await?);
+        if let Some(proj) = project {
+            req = req.header("x-goog-user-project", proj);
+            if requester_pays {
+                req = req.query(&[("userProject", proj)]);
+            }
+        }
+    }
+    let resp = req.send().await.map_err(|e| {
+        PyIOError::new_err(format!("GCS ERROR: {}: {}", path, e))
+    })?;
+    let status = resp.status().as_u16();
+    let body = resp.bytes().await.map_err(|e| {
+        PyIOError::new_err(format!("GCS ERROR: {}: {}", path, e))
+    })?;
+    match status {
+        404 => Ok(None),
+        // the bucket already exists, or is not empty
+        409 => Err(PyFileExistsError::new_err(format!(
+            "{}: {}",
+            path,
+            String::from_utf8_lossy(&body)
+        ))),
+        s if s >= 400 => {
+            Err(gcs_err(path, s, &String::from_utf8_lossy(&body)))
+        }
+        _ if body.is_empty() => Ok(Some(serde_json::Value::Null)),
+        _ => serde_json::from_slice(&body)
+            .map(Some)
+            .map_err(|e| gcs_err(path, status, &e.to_string())),
+    }
+}
+
+/// every bucket in the project, as directories
 #[pyfunction]
-#[pyo3(signature = (path, region=None, profile=None, endpoint_url=None, anon=false, requester_pays=false))]
-fn s3_info(
-    py: Python, path: &str, region: Option<&str>, profile: Option<&str>,
-    endpoint_url: Option<&str>, anon: bool, requester_pays: bool,
-) -> HashMap<String, String> {
-    let mut output: HashMap<String, String> = HashMap::new();
+#[pyo3(signature = (project, anon=false))]
+fn gcs_list_buckets(
+    py: Python, project: &str, anon: bool,
+) -> PyResult<Vec<HashMap<String, String>>> {
     let coroutine = async {
-        let s3_client = s3(region, profile, endpoint_url).await;
-        let out = path.split_once("/");
-        match out {
-            None => output
-                .insert("error".to_string(), "S3 ERROR: bad path".to_string()),
-            Some((bucket, key)) => {
-                let mut resp = s3_client.head_object().bucket(bucket).key(key);
-                if requester_pays {
-                    resp = resp.request_payer(RequestPayer::Requester);
+        let mut output: Vec<HashMap<String, String>> = Vec::new();
+        let mut page_token: Option<String> = None;
+        loop {
+            let mut req = CLIENT
+                .get(format!("{}/storage/v1/b", gcs_host()))
+                .query(&[("project", project)]);
+            if let Some(t) = &page_token {
+                req = req.query(&[("pageToken", t)]);
+            }
+            let page = gcs_json("", req, anon, Some(project), false).await?;
+            for b in page["items"].as_array().into_iter().flatten() {
+                let mut h: HashMap<String, String> = HashMap::new();
+                h.insert(
+                    "name".to_string(),
+                    b["name"].as_str().unwrap_or("").into(),
+                );
+                h.insert("size".to_string(), "0".to_string());
+                h.insert("type".to_string(), "directory".to_string());
+                if let Some(t) = b["timeCreated"].as_str() {
+                    h.insert("timeCreated".to_string(), t.into());
                 }
-                let resp = if anon {
-                    resp.customize()
-                        .await
-                        .unwrap()
-                        .map_operation(make_unsigned)
-                        .unwrap()
-                        .send()
-                        .await
-                } else {
-                    resp.send().await
-                };
-                let resp = resp.unwrap();
-                output.insert(
-                    "size".to_string(),
-                    format!("{}", resp.content_length()),
-                )
+                output.push(h);
             }
-        };
+            page_token = page["nextPageToken"].as_str().map(|t| t.to_string());
+            if page_token.is_none() {
+                break;
+            }
+        }
+        Ok::<_, PyErr>(output)
     };
-    py.allow_threads(|| RUNTIME.block_on(coroutine));
-    output
+    py.allow_threads(|| RUNTIME.block_on(coroutine))
 }
 
+/// create a bucket in the project, in the given location (e.g., "EU") or
+/// else the default, "US"
 #[pyfunction]
-fn s3_cat_ranges<'py>(
-    py: Python<'py>, path: Vec<&str>, start: Vec<i64>, end: Vec<i64>,
-    anon: bool, requester_pays: bool, profile: Option<&str>,
-    endpoint_url: Option<&str>, region: Option<&str>,
-) -> &'py PyTuple {
-    let mut result: Vec<Vec<u8>> = Vec::with_capacity(path.len());
-    let coroutine = async {
-        let s3_client = s3(region, profile, endpoint_url).await;
-        join_all(path.iter().zip(start).zip(end).map(|((u, st), e)| {
-            s3_get_one_range(u, s3_client.clone(), st, e, requester_pays, anon)
-        }))
-        .await
-        .into_iter()
-        .map(|out: Vec<u8>| result.push(out))
-        .count()
-    };
-    py.allow_threads(|| RUNTIME.block_on(coroutine));
-    PyTuple::new(py, result.iter().map(|r| PyBytes::new(py, &r[..])))
+#[pyo3(signature = (bucket, project, location=None, anon=false))]
+fn gcs_create_bucket(
+    py: Python, bucket: &str, project: &str, location: Option<&str>,
+    anon: bool,
+) -> PyResult<()> {
+    let mut spec = serde_json::json!({ "name": bucket });
+    if let Some(loc) = location {
+        spec["location"] = loc.into();
+    }
+    let req = CLIENT
+        .post(format!("{}/storage/v1/b", gcs_host()))
+        .query(&[("project", project)])
+        .header("content-type", "application/json")
+        .body(spec.to_string());
+    let coroutine = gcs_json(bucket, req, anon, Some(project), false);
+    py.allow_threads(|| RUNTIME.block_on(coroutine))?;
+    Ok(())
 }
 
-async fn gcs() -> TokenManager {
-    let cname: &str = "full-control";
-    if GCS_TOKEN.lock().unwrap().contains_key(cname) {
-        // clone is free since "client" is actually an Arc pointing to real object
-        return GCS_TOKEN.lock().unwrap().get(cname).unwrap().clone();
-    }
-    let tok = TokenManager::new(&[cname]).await.unwrap();
-    GCS_TOKEN.lock().unwrap().insert(cname.to_string(), tok.clone());
-    tok
+/// delete a bucket, which must be empty
+#[pyfunction]
+#[pyo3(signature = (bucket, requester_pays=false, anon=false, project=None))]
+fn gcs_delete_bucket(
+    py: Python, bucket: &str, requester_pays: bool, anon: bool,
+    project: Option<&str>,
+) -> PyResult<()> {
+    let req = CLIENT.delete(format!("{}/storage/v1/b/{}", gcs_host(), bucket));
+    let coroutine = gcs_json(bucket, req, anon, project, requester_pays);
+    py.allow_threads(|| RUNTIME.block_on(coroutine))?;
+    Ok(())
 }
 
-async fn gcs_get_range(
-    path: &str, tok: Option<String>, start: usize, end: usize,
-    project: Option<&str>, requester_pays: bool,
-) -> Bytes {
-    let mut head: HashMap<&str, String> = HashMap::new();
-    let mut extra: String = String::new();
+/// delete the object at "bucket/name"
+#[pyfunction]
+#[pyo3(signature = (path, requester_pays=false, anon=false, project=None))]
+fn gcs_delete(
+    py: Python, path: &str, requester_pays: bool, anon: bool,
+    project: Option<&str>,
+) -> PyResult<()> {
+    let (bucket, key) = path
+        .split_once("/")
+        .ok_or_else(|| PyValueError::new_err("GCS ERROR: bad path"))?;
+    let req = CLIENT.delete(format!(
+        "{}/storage/v1/b/{}/o/{}",
+        gcs_host(),
+        bucket,
+        encode(key)
+    ));
+    let coroutine = gcs_json(path, req, anon, project, requester_pays);
+    py.allow_threads(|| RUNTIME.block_on(coroutine))?;
+    Ok(())
+}
+
+/// info of many objects, "bucket/name", with up to `concurrency` requests
+/// at once: a list in the order of paths, with None for any that does not
+/// exist
+#[pyfunction]
+#[pyo3(signature = (paths, concurrency=32, requester_pays=false, anon=false, project=None))]
+fn gcs_info_many(
+    py: Python, paths: Vec<&str>, concurrency: usize, requester_pays: bool,
+    anon: bool, project: Option<&str>,
+) -> PyResult<Vec<Option<HashMap<String, String>>>> {
+    let paths: Vec<String> = paths.into_iter().map(String::from).collect();
+    let coroutine = async {
+        futures::stream::iter(paths)
+            .map(|path| async move {
+                let path: &str = &path;
+                let (bucket, key) = path.split_once("/").ok_or_else(|| {
+                    PyValueError::new_err("GCS ERROR: bad path")
+                })?;
+                let req = CLIENT.get(format!(
+                    "{}/storage/v1/b/{}/o/{}",
+                    gcs_host(),
+                    bucket,
+                    encode(key)
+                ));
+                let meta =
+                    gcs_json(path, req, anon, project, requester_pays).await?;
+                let mut h: HashMap<String, String> = HashMap::new();
+                h.insert(
+                    "size".to_string(),
+                    meta["size"].as_str().unwrap_or("0").to_string(),
+                );
+                h.insert("type".to_string(), "file".to_string());
+                for (k, field) in [
+                    ("ETag", "etag"),
+                    ("generation", "generation"),
+                    ("updated", "updated"),
+                ] {
+                    if let Some(v) = meta[field].as_str() {
+                        h.insert(k.to_string(), v.to_string());
+                    }
+                }
+                Ok::<_, PyErr>(h)
+            })
+            .buffered(concurrency.max(1))
+            .collect::<Vec<PyResult<_>>>()
+            .await
+    };
+    let results = py.allow_threads(|| RUNTIME.block_on(coroutine));
+    results.into_iter().map(|r| missing_as_none(py, r)).collect()
+}
+
+/// one page of objects with names starting prefix and, if delimited, the
+/// prefixes one level below it; with the token for the next page
+#[allow(clippy::too_many_arguments)]
+async fn gcs_list_page(
+    bucket: &str, prefix: &str, delimited: bool, tok: Option<String>,
+    project: Option<&str>, requester_pays: bool, page_token: Option<&str>,
+) -> PyResult<(Listing, Option<String>)> {
+    let path = format!("{}/{}", bucket, prefix);
+    let mut req =
+        CLIENT.get(format!("{}/storage/v1/b/{}/o", gcs_host(), bucket));
+    req = req.query(&[("prefix", prefix)]);
+    if delimited {
+        req = req.query(&[("delimiter", "/")]);
+    }
+    if let Some(t) = page_token {
+        req = req.query(&[("pageToken", t)]);
+    }
     if let Some(tok_str) = tok {
-        head.insert("authorization", tok_str);
+        req = req.header("authorization", tok_str);
         if let Some(proj) = project {
-            head.insert("x-goog-user-project", proj.to_string());
+            req = req.header("x-goog-user-project", proj);
             if requester_pays {
-                extra.extend(format!("&userProject={}", proj).chars());
+                req = req.query(&[("userProject", proj)]);
             }
         };
     }
-    let (bucket, key) = path.split_once("/").unwrap();
-    // or STORAGE_EMULATOR_HOST env var for testing
-    let host = "https://storage.googleapis.com";
-    let url = format!(
-        "{}/download/storage/v1/b/{}/o/{}?alt=media{}",
-        host,
-        bucket,
-        encode(key),
-        extra
-    );
-    get_url_or(&url[..], start, end, head, &reqwest::Method::GET).await
+    let resp = req.send().await.map_err(|e| {
+        PyIOError::new_err(format!("GCS ERROR: {}: {}", path, e))
+    })?;
+    let status = resp.status().as_u16();
+    let body = resp.bytes().await.map_err(|e| {
+        PyIOError::new_err(format!("GCS ERROR: {}: {}", path, e))
+    })?;
+    if status >= 400 {
+        return Err(gcs_err(&path, status, &String::from_utf8_lossy(&body)));
+    }
+    let page: serde_json::Value = serde_json::from_slice(&body)
+        .map_err(|e| gcs_err(&path, status, &e.to_string()))?;
+    let mut files: Vec<HashMap<String, String>> = Vec::new();
+    for item in page["items"].as_array().into_iter().flatten() {
+        let mut h: HashMap<String, String> = HashMap::new();
+        h.insert(
+            "name".to_string(),
+            format!("{}/{}", bucket, item["name"].as_str().unwrap_or("")),
+        );
+        h.insert(
+            "size".to_string(),
+            item["size"].as_str().unwrap_or("0").to_string(),
+        );
+        h.insert("type".to_string(), "file".to_string());
+        files.push(h);
+    }
+    let prefixes: Vec<String> = page["prefixes"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|p| p.as_str().map(|p| p.to_string()))
+        .collect();
+    let next = page["nextPageToken"].as_str().map(|t| t.to_string());
+    Ok(((files, prefixes), next))
+}
+
+/// every object with names starting prefix and, if delimited, the prefixes
+/// one level below it
+async fn gcs_list_all(
+    bucket: &str, prefix: &str, delimited: bool, tok: Option<String>,
+    project: Option<&str>, requester_pays: bool,
+) -> PyResult<Listing> {
+    let mut files: Vec<HashMap<String, String>> = Vec::new();
+    let mut prefixes: Vec<String> = Vec::new();
+    let mut page_token: Option<String> = None;
+    loop {
+        let ((f, p), next) = gcs_list_page(
+            bucket,
+            prefix,
+            delimited,
+            tok.clone(),
+            project,
+            requester_pays,
+            page_token.as_deref(),
+        )
+        .await?;
+        files.extend(f);
+        prefixes.extend(p);
+        page_token = next;
+        if page_token.is_none() {
+            return Ok((files, prefixes));
+        }
+    }
+}
+
+/// total size of the objects below "bucket/prefix" or, if not total, a dict
+/// of the total in each directory, the root included
+///
+/// maxdepth: levels below the root to count files from
+#[pyfunction]
+#[pyo3(signature = (path, total=true, maxdepth=None, requester_pays=false, anon=false, project=None))]
+fn gcs_du(
+    py: Python, path: &str, total: bool, maxdepth: Option<usize>,
+    requester_pays: bool, anon: bool, project: Option<&str>,
+) -> PyResult<PyObject> {
+    let (bucket, key) = path.split_once("/").unwrap_or((path, ""));
+    let prefix = match key.trim_end_matches('/') {
+        "" => String::new(),
+        k => format!("{}/", k),
+    };
+    let coroutine = async {
+        let tok: Option<String> = match anon {
+            true => None,
+            false => Some(gcs_token().await?),
+        };
+        let (files, _) =
+            gcs_list_all(bucket, &prefix, false, tok, project, requester_pays)
+                .await?;
+        let mut usage = Usage::new(path, maxdepth, !total);
+        let skip = bucket.len() + 1 + prefix.len();
+        for info in files.iter() {
+            usage
+                .add(&info["name"][skip..], info["size"].parse().unwrap_or(0));
+        }
+        // nothing below the path: it may be an object itself
+        if files.is_empty() && !prefix.is_empty() {
+            let req = CLIENT.get(format!(
+                "{}/storage/v1/b/{}/o/{}",
+                gcs_host(),
+                bucket,
+                encode(key.trim_end_matches('/'))
+            ));
+            let meta =
+                gcs_json_found(path, req, anon, project, requester_pays)
+                    .await?;
+            if let Some(meta) = meta {
+                usage.add_root(
+                    meta["size"].as_str().unwrap_or("0").parse().unwrap_or(0),
+                );
+            }
+        }
+        Ok::<_, PyErr>(usage)
+    };
+    Ok(py.allow_threads(|| RUNTIME.block_on(coroutine))?.into_py(py))
 }
 
+/// files and directories matching a glob pattern, "bucket/name-pattern",
+/// sorted by name, and with maxdepth, at most that many levels below the
+/// pattern's literal directory
 #[pyfunction]
-fn gcs_cat_ranges<'py>(
-    py: Python<'py>, path: Vec<&str>, start: Vec<usize>, end: Vec<usize>,
+#[pyo3(signature = (path, maxdepth=None, requester_pays=false, anon=false, project=None))]
+fn gcs_glob<'py>(
+    py: Python<'py>, path: &str, maxdepth: Option<usize>,
     requester_pays: bool, anon: bool, project: Option<&str>,
-) -> &'py PyTuple {
-    let mut result: Vec<Bytes> = Vec::with_capacity(path.len());
+) -> PyResult<&'py PyTuple> {
+    let (bucket, key) = path.split_once("/").unwrap_or((path, ""));
+    let pattern = Pattern::new(key);
     let coroutine = async {
         let tok: Option<String> = match anon {
             true => None,
-            false => Some(gcs().await.token().await.unwrap()),
+            false => Some(gcs_token().await?),
         };
-        join_all(path.iter().zip(start).zip(end).map(|((u, st), e)| {
-            gcs_get_range(u, tok.clone(), st, e, project, requester_pays)
-        }))
+        let tok = &tok;
+        glob(bucket, &pattern, maxdepth, |prefix, delimited| async move {
+            gcs_list_all(
+                bucket,
+                &prefix,
+                delimited,
+                tok.clone(),
+                project,
+                requester_pays,
+            )
+            .await
+        })
         .await
-        .into_iter()
-        .map(|out: Bytes| result.push(out))
-        .count()
     };
-    py.allow_threads(|| RUNTIME.block_on(coroutine));
-    PyTuple::new(py, result.iter().map(|r| PyBytes::new(py, &r[..])))
+    let output = py.allow_threads(|| RUNTIME.block_on(coroutine))?;
+    Ok(PyTuple::new(py, output.iter().map(|r| r.to_object(py))))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// a V4-signed URL giving access to "bucket/name" with the given HTTP method
+/// for `expiration` seconds, without credentials. Signing needs a service
+/// account's key file, given or from GOOGLE_APPLICATION_CREDENTIALS.
+#[pyfunction]
+#[pyo3(signature = (path, expiration=100, method="GET", credentials=None))]
+fn gcs_sign(
+    path: &str, expiration: u64, method: &str, credentials: Option<String>,
+) -> PyResult<String> {
+    let (bucket, key) = path
+        .split_once("/")
+        .ok_or_else(|| PyValueError::new_err("GCS ERROR: bad path"))?;
+    let key_file = credentials
+        .or_else(|| std::env::var("GOOGLE_APPLICATION_CREDENTIALS").ok())
+        .ok_or_else(|| {
+            PyValueError::new_err("signing needs a service account key file")
+        })?;
+    let account: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&key_file)?).map_err(|e| {
+            PyValueError::new_err(format!("{}: {}", key_file, e))
+        })?;
+    let (Some(email), Some(pem)) =
+        (account["client_email"].as_str(), account["private_key"].as_str())
+    else {
+        return Err(PyValueError::new_err(format!(
+            "{}: not a service account key",
+            key_file
+        )));
+    };
+    let private_key = RsaPrivateKey::from_pkcs8_pem(pem)
+        .map_err(|e| PyValueError::new_err(format!("{}: {}", key_file, e)))?;
+
+    // e.g., 20230401T120000Z
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let timestamp = DateTime::from_secs(now.as_secs() as i64)
+        .fmt(DateTimeFormat::DateTime)
+        .unwrap()
+        .replace(['-', ':'], "");
+    let scope = format!("{}/auto/storage/goog4_request", &timestamp[..8]);
+    // the endpoint's authority is the signed host header
+    let base = gcs_host();
+    let host = base.split_once("://").map_or(base.as_str(), |(_, h)| h);
+    let resource = format!(
+        "/{}/{}",
+        bucket,
+        key.split('/').map(|k| encode(k)).collect::<Vec<_>>().join("/")
+    );
+    // parameters are given sorted, as the canonical form requires
+    let query = [
+        ("X-Goog-Algorithm", "GOOG4-RSA-SHA256".to_string()),
+        ("X-Goog-Credential", format!("{}/{}", email, scope)),
+        ("X-Goog-Date", timestamp.clone()),
+        ("X-Goog-Expires", expiration.to_string()),
+        ("X-Goog-SignedHeaders", "host".to_string()),
+    ]
+    .iter()
+    .map(|(k, v)| format!("{}={}", k, encode(v)))
+    .collect::<Vec<_>>()
+    .join("&");
+    let request = format!(
+        "{}\n{}\n{}\nhost:{}\n\nhost\nUNSIGNED-PAYLOAD",
+        method.to_uppercase(),
+        resource,
+        query,
+        host
+    );
+    let to_sign = format!(
+        "GOOG4-RSA-SHA256\n{}\n{}\n{}",
+        timestamp,
+        scope,
+        hex(&Sha256::digest(request.as_bytes()))
+    );
+    let signature = SigningKey::<Sha256>::new(private_key)
+        .sign(to_sign.as_bytes())
+        .to_vec();
+    Ok(format!(
+        "{}{}?{}&X-Goog-Signature={}",
+        base,
+        resource,
+        query,
+        hex(&signature)
+    ))
 }
 
+use azure_core::headers::{Headers, IF_MATCH, IF_NONE_MATCH};
 use azure_core::request_options::Range as ARange;
+use azure_core::request_options::{Delimiter, IfMatchCondition, MaxResults};
+use azure_core::{Context, CustomHeaders, Pageable};
 use azure_storage::prelude::StorageCredentials;
+use azure_storage::shared_access_signature::service_sas::BlobSasPermissions;
+use azure_storage::EMULATOR_ACCOUNT;
+use azure_storage_blobs::container::operations::ListBlobsResponse;
 use azure_storage_blobs::prelude::ClientBuilder;
 use futures::StreamExt;
+use std::num::NonZeroU32;
+use time::OffsetDateTime;
+
+/// Clients for the account; Azurite's well-known account, for testing, is
+/// served by the emulator on localhost
+fn azure_builder(account: String, cred: StorageCredentials) -> ClientBuilder {
+    match account.as_str() {
+        EMULATOR_ACCOUNT => ClientBuilder::emulator(),
+        _ => ClientBuilder::new(account, cred),
+    }
+}
 
+/// With if_match, a failed condition means that the blob was replaced, and
+/// is raised rather than returned as error bytes
 async fn azure_get_range(
     client: ClientBuilder, path: &str, start: usize, end: usize,
-) -> Vec<u8> {
+    if_match: Option<String>,
+) -> PyResult<Vec<u8>> {
     let (container, key) = path.split_once("/").unwrap();
     let blob = client.blob_client(container, key);
     let mut out = Vec::new();
-    let getter = if (start > 0) | (end > 0) {
+    let mut getter = if (start > 0) | (end > 0) {
         blob.get().range(ARange::new(start as u64, end as u64))
     } else {
         blob.get()
     };
+    if let Some(etag) = &if_match {
+        getter = getter.if_match(IfMatchCondition::Match(etag.clone()));
+    }
     let mut stream = getter.into_stream();
     while let Some(value) = stream.next().await {
+        if let Err(e) = &value {
+            if e.as_http_error().map(|h| h.status() as u16) == Some(412) {
+                return Err(FileChangedError::new_err(format!(
+                    "{} changed since it was opened (ETag {})",
+                    path,
+                    if_match.unwrap_or_default()
+                )));
+            }
+        }
         // could have been done neater with ? operator in a try block
         match value {
             Ok(data) => match data.data.collect().await {
@@ -732,55 +4402,664 @@ async fn azure_get_range(
             Err(e) => out.extend(format!("AZURE ERROR: {}", e).as_bytes()),
         }
     }
-    out
+    Ok(out)
 }
 
+/// if_match: optional list, one per path, of the ETag the blob must still
+/// have; raises FileChangedError if any has been replaced
 #[pyfunction]
+#[allow(clippy::too_many_arguments)]
 fn azure_cat_ranges<'py>(
     py: Python<'py>, path: Vec<&str>, start: Vec<usize>, end: Vec<usize>,
     anon: bool, account: String, key: Option<String>,
-) -> &'py PyTuple {
-    let mut result: Vec<Vec<u8>> = Vec::with_capacity(path.len());
+    if_match: Option<Vec<Option<String>>>,
+) -> PyResult<&'py PyTuple> {
+    let if_match = if_match.unwrap_or_else(|| vec![None; path.len()]);
     let cred = match anon {
         true => StorageCredentials::Anonymous,
         false => StorageCredentials::Key(account.clone(), key.unwrap()),
     };
     // TODO: some part of the client creation should be cached; `client` here is
     //  only a "builder" so probably nothing has happened yet
-    let client = ClientBuilder::new(account, cred);
+    let client = azure_builder(account, cred);
     let coroutine = async {
-        join_all(
-            path.iter()
-                .zip(start)
-                .zip(end)
-                .map(|((u, st), e)| azure_get_range(client.clone(), u, st, e)),
-        )
+        join_all(path.iter().zip(start).zip(end).zip(if_match).map(
+            |(((u, st), e), m)| azure_get_range(client.clone(), u, st, e, m),
+        ))
         .await
         .into_iter()
-        .map(|out| result.push(out))
-        .count()
+        .collect::<PyResult<Vec<Vec<u8>>>>()
     };
-    py.allow_threads(|| RUNTIME.block_on(coroutine));
-    PyTuple::new(py, result.iter().map(|r| PyBytes::new(py, &r[..])))
+    let result = py.allow_threads(|| RUNTIME.block_on(coroutine))?;
+    Ok(PyTuple::new(py, result.iter().map(|r| PyBytes::new(py, &r[..]))))
+}
+
+fn azure_err(path: &str, err: azure_core::Error) -> PyErr {
+    match err.as_http_error().map(|e| e.status() as u16) {
+        Some(404) => PyFileNotFoundError::new_err(path.to_string()),
+        Some(401) | Some(403) => {
+            PyPermissionError::new_err(format!("{}: {}", path, err))
+        }
+        _ => {
+            PyRuntimeError::new_err(format!("AZURE ERROR: {}: {}", path, err))
+        }
+    }
+}
+
+/// upload bytes to "container/name", returning its ETag
+///
+/// if_none_match, if_match: write only if the blob does not exist ("*"), or
+/// has this ETag; else raise PreconditionFailedError
+#[pyfunction]
+#[pyo3(signature = (path, data, account, anon=false, key=None, if_none_match=None, if_match=None))]
+#[allow(clippy::too_many_arguments)]
+fn azure_pipe(
+    py: Python, path: &str, data: &PyAny, account: String, anon: bool,
+    key: Option<String>, if_none_match: Option<&str>, if_match: Option<&str>,
+) -> PyResult<HashMap<String, String>> {
+    let cond = WriteCondition::new(if_none_match, if_match)?;
+    let (container, name) = path
+        .split_once("/")
+        .ok_or_else(|| PyValueError::new_err("AZURE ERROR: bad path"))?;
+    let cred = match (anon, key) {
+        (false, Some(key)) => StorageCredentials::Key(account.clone(), key),
+        _ => StorageCredentials::Anonymous,
+    };
+    let client = azure_builder(account, cred);
+    let data: &[u8] = py_to_byteslice(data);
+    let coroutine = async {
+        // the builder has no conditions, so send them as custom headers
+        let mut headers = Headers::new();
+        if let Some(etag) = &cond.if_match {
+            headers.insert(IF_MATCH, etag);
+        }
+        if let Some(any) = &cond.if_none_match {
+            headers.insert(IF_NONE_MATCH, any);
+        }
+        let mut ctx = Context::new();
+        ctx.insert(CustomHeaders::from(headers));
+        client
+            .blob_client(container, name)
+            .put_block_blob(Bytes::from_static(data))
+            .context(ctx)
+            .await
+    };
+    match py.allow_threads(|| RUNTIME.block_on(coroutine)) {
+        Ok(resp) => {
+            let mut h: HashMap<String, String> = HashMap::new();
+            h.insert("ETag".to_string(), resp.etag.to_string());
+            Ok(h)
+        }
+        // an existing blob fails "*" with 409, a changed one 412
+        Err(e) => match e.as_http_error().map(|h| h.status() as u16) {
+            Some(409) | Some(412) if !cond.is_none() => {
+                Err(precondition_err(path))
+            }
+            _ => Err(azure_err(path, e)),
+        },
+    }
+}
+
+/// every container in the account, as directories
+#[pyfunction]
+#[pyo3(signature = (account, anon=false, key=None))]
+fn azure_list_containers(
+    py: Python, account: String, anon: bool, key: Option<String>,
+) -> PyResult<Vec<HashMap<String, String>>> {
+    let cred = match (anon, key) {
+        (false, Some(key)) => StorageCredentials::Key(account.clone(), key),
+        _ => StorageCredentials::Anonymous,
+    };
+    let client = azure_builder(account, cred).blob_service_client();
+    let coroutine = async {
+        let mut output: Vec<HashMap<String, String>> = Vec::new();
+        let mut pages = client.list_containers().into_stream();
+        while let Some(page) = pages.next().await {
+            let page = page.map_err(|e| azure_err("", e))?;
+            for c in page.containers {
+                let mut h: HashMap<String, String> = HashMap::new();
+                h.insert("name".to_string(), c.name);
+                h.insert("size".to_string(), "0".to_string());
+                h.insert("type".to_string(), "directory".to_string());
+                output.push(h);
+            }
+        }
+        Ok::<_, PyErr>(output)
+    };
+    py.allow_threads(|| RUNTIME.block_on(coroutine))
+}
+
+/// create a private container
+#[pyfunction]
+#[pyo3(signature = (container, account, key))]
+fn azure_create_container(
+    py: Python, container: &str, account: String, key: String,
+) -> PyResult<()> {
+    let cred = StorageCredentials::Key(account.clone(), key);
+    let client = azure_builder(account, cred).container_client(container);
+    py.allow_threads(|| RUNTIME.block_on(client.create().into_future()))
+        .map_err(|e| match e.as_http_error().map(|e| e.status() as u16) {
+            Some(409) => PyFileExistsError::new_err(container.to_string()),
+            _ => azure_err(container, e),
+        })?;
+    Ok(())
+}
+
+/// delete a container, which must be empty
+#[pyfunction]
+#[pyo3(signature = (container, account, key))]
+fn azure_delete_container(
+    py: Python, container: &str, account: String, key: String,
+) -> PyResult<()> {
+    let cred = StorageCredentials::Key(account.clone(), key);
+    let client = azure_builder(account, cred).container_client(container);
+    let coroutine = async {
+        // Azure deletes a container with its blobs, unlike a bucket
+        let mut pages = client
+            .list_blobs()
+            .max_results(MaxResults::new(NonZeroU32::MIN))
+            .into_stream();
+        if let Some(page) = pages.next().await {
+            let page = page.map_err(|e| azure_err(container, e))?;
+            if !page.blobs.items.is_empty() {
+                return Err(PyFileExistsError::new_err(format!(
+                    "{}: container is not empty",
+                    container
+                )));
+            }
+        }
+        client.delete().await.map_err(|e| azure_err(container, e))
+    };
+    py.allow_threads(|| RUNTIME.block_on(coroutine))?;
+    Ok(())
+}
+
+/// delete the blob at "container/name"
+#[pyfunction]
+#[pyo3(signature = (path, account, anon=false, key=None))]
+fn azure_delete(
+    py: Python, path: &str, account: String, anon: bool, key: Option<String>,
+) -> PyResult<()> {
+    let (container, name) = path
+        .split_once("/")
+        .ok_or_else(|| PyValueError::new_err("AZURE ERROR: bad path"))?;
+    let cred = match (anon, key) {
+        (false, Some(key)) => StorageCredentials::Key(account.clone(), key),
+        _ => StorageCredentials::Anonymous,
+    };
+    let client = azure_builder(account, cred).blob_client(container, name);
+    py.allow_threads(|| RUNTIME.block_on(client.delete().into_future()))
+        .map_err(|e| azure_err(path, e))?;
+    Ok(())
+}
+
+/// info of many blobs, "container/name", with up to `concurrency` requests
+/// at once: a list in the order of paths, with None for any that does not
+/// exist
+#[pyfunction]
+#[pyo3(signature = (paths, account, concurrency=32, anon=false, key=None))]
+fn azure_info_many(
+    py: Python, paths: Vec<&str>, account: String, concurrency: usize,
+    anon: bool, key: Option<String>,
+) -> PyResult<Vec<Option<HashMap<String, String>>>> {
+    let cred = match (anon, key) {
+        (false, Some(key)) => StorageCredentials::Key(account.clone(), key),
+        _ => StorageCredentials::Anonymous,
+    };
+    let client = azure_builder(account, cred);
+    let paths: Vec<String> = paths.into_iter().map(String::from).collect();
+    let coroutine = async {
+        let client = &client;
+        futures::stream::iter(paths)
+            .map(|path| async move {
+                let path: &str = &path;
+                let (container, name) =
+                    path.split_once("/").ok_or_else(|| {
+                        PyValueError::new_err("AZURE ERROR: bad path")
+                    })?;
+                let props = client
+                    .clone()
+                    .blob_client(container, name)
+                    .get_properties()
+                    .await
+                    .map_err(|e| azure_err(path, e))?
+                    .blob
+                    .properties;
+                let mut h: HashMap<String, String> = HashMap::new();
+                h.insert("size".to_string(), props.content_length.to_string());
+                h.insert("type".to_string(), "file".to_string());
+                h.insert("ETag".to_string(), props.etag.to_string());
+                Ok::<_, PyErr>(h)
+            })
+            .buffered(concurrency.max(1))
+            .collect::<Vec<PyResult<_>>>()
+            .await
+    };
+    let results = py.allow_threads(|| RUNTIME.block_on(coroutine));
+    results.into_iter().map(|r| missing_as_none(py, r)).collect()
+}
+
+/// The pages of a blob listing, following the continuation marker
+type BlobPages = Pageable<ListBlobsResponse, azure_core::Error>;
+
+fn azure_blob_pages(
+    client: ClientBuilder, container: &str, prefix: &str, delimited: bool,
+) -> BlobPages {
+    let mut req = client
+        .container_client(container)
+        .list_blobs()
+        .prefix(prefix.to_string());
+    if delimited {
+        req = req.delimiter(Delimiter::new("/"));
+    }
+    req.into_stream()
+}
+
+/// the next page of blobs and prefixes, and whether more pages follow; None
+/// once the listing is exhausted
+async fn azure_list_page(
+    pages: &mut BlobPages, container: &str, prefix: &str,
+) -> PyResult<Option<(Listing, bool)>> {
+    let page = match pages.next().await {
+        Some(page) => page
+            .map_err(|e| azure_err(&format!("{}/{}", container, prefix), e))?,
+        None => return Ok(None),
+    };
+    let mut files: Vec<HashMap<String, String>> = Vec::new();
+    for blob in page.blobs.blobs() {
+        let mut h: HashMap<String, String> = HashMap::new();
+        h.insert("name".to_string(), format!("{}/{}", container, blob.name));
+        h.insert(
+            "size".to_string(),
+            blob.properties.content_length.to_string(),
+        );
+        h.insert("type".to_string(), "file".to_string());
+        files.push(h);
+    }
+    let prefixes: Vec<String> =
+        page.blobs.prefixes().map(|p| p.name.clone()).collect();
+    Ok(Some(((files, prefixes), page.next_marker.is_some())))
+}
+
+/// every blob with names starting prefix and, if delimited, the prefixes
+/// one level below it
+async fn azure_list_all(
+    client: ClientBuilder, container: &str, prefix: &str, delimited: bool,
+) -> PyResult<Listing> {
+    let mut files: Vec<HashMap<String, String>> = Vec::new();
+    let mut prefixes: Vec<String> = Vec::new();
+    let mut pages = azure_blob_pages(client, container, prefix, delimited);
+    while let Some(((f, p), _)) =
+        azure_list_page(&mut pages, container, prefix).await?
+    {
+        files.extend(f);
+        prefixes.extend(p);
+    }
+    Ok((files, prefixes))
+}
+
+/// files and directories matching a glob pattern, "container/name-pattern",
+/// sorted by name, and with maxdepth, at most that many levels below the
+/// pattern's literal directory
+#[pyfunction]
+#[pyo3(signature = (path, account, maxdepth=None, anon=false, key=None))]
+fn azure_glob<'py>(
+    py: Python<'py>, path: &str, account: String, maxdepth: Option<usize>,
+    anon: bool, key: Option<String>,
+) -> PyResult<&'py PyTuple> {
+    let cred = match (anon, key) {
+        (false, Some(key)) => StorageCredentials::Key(account.clone(), key),
+        _ => StorageCredentials::Anonymous,
+    };
+    let client = azure_builder(account, cred);
+    let (container, key) = path.split_once("/").unwrap_or((path, ""));
+    let pattern = Pattern::new(key);
+    let coroutine =
+        glob(container, &pattern, maxdepth, |prefix, delimited| {
+            let client = client.clone();
+            async move {
+                azure_list_all(client, container, &prefix, delimited).await
+            }
+        });
+    let output = py.allow_threads(|| RUNTIME.block_on(coroutine))?;
+    Ok(PyTuple::new(py, output.iter().map(|r| r.to_object(py))))
+}
+
+/// total size of the blobs below "container/prefix" or, if not total, a dict
+/// of the total in each directory, the root included
+///
+/// maxdepth: levels below the root to count files from
+#[pyfunction]
+#[pyo3(signature = (path, account, total=true, maxdepth=None, anon=false, key=None))]
+fn azure_du(
+    py: Python, path: &str, account: String, total: bool,
+    maxdepth: Option<usize>, anon: bool, key: Option<String>,
+) -> PyResult<PyObject> {
+    let cred = match (anon, key) {
+        (false, Some(key)) => StorageCredentials::Key(account.clone(), key),
+        _ => StorageCredentials::Anonymous,
+    };
+    let client = azure_builder(account, cred);
+    let (container, key) = path.split_once("/").unwrap_or((path, ""));
+    let prefix = match key.trim_end_matches('/') {
+        "" => String::new(),
+        k => format!("{}/", k),
+    };
+    let coroutine = async {
+        let (files, _) =
+            azure_list_all(client.clone(), container, &prefix, false).await?;
+        let mut usage = Usage::new(path, maxdepth, !total);
+        let skip = container.len() + 1 + prefix.len();
+        for info in files.iter() {
+            usage
+                .add(&info["name"][skip..], info["size"].parse().unwrap_or(0));
+        }
+        // nothing below the path: it may be a blob itself
+        if files.is_empty() && !prefix.is_empty() {
+            let blob = client
+                .blob_client(container, key.trim_end_matches('/'))
+                .get_properties()
+                .await;
+            match blob {
+                Ok(b) => usage.add_root(b.blob.properties.content_length),
+                Err(e)
+                    if e.as_http_error().map(|h| h.status() as u16)
+                        == Some(404) => {}
+                Err(e) => return Err(azure_err(path, e)),
+            }
+        }
+        Ok::<_, PyErr>(usage)
+    };
+    Ok(py.allow_threads(|| RUNTIME.block_on(coroutine))?.into_py(py))
+}
+
+/// a SAS URL giving access to "container/name" with the given HTTP method
+/// for `expiration` seconds, signed with the account key
+#[pyfunction]
+#[pyo3(signature = (path, account, key, expiration=100, method="GET"))]
+fn azure_sign(
+    path: &str, account: String, key: String, expiration: i64, method: &str,
+) -> PyResult<String> {
+    let (container, name) = path
+        .split_once("/")
+        .ok_or_else(|| PyValueError::new_err("AZURE ERROR: bad path"))?;
+    let permissions = match method.to_uppercase().as_str() {
+        "GET" | "HEAD" => {
+            BlobSasPermissions { read: true, ..Default::default() }
+        }
+        "PUT" => BlobSasPermissions {
+            write: true,
+            create: true,
+            ..Default::default()
+        },
+        "DELETE" => BlobSasPermissions { delete: true, ..Default::default() },
+        other => {
+            return Err(PyValueError::new_err(format!(
+                "cannot sign method {}",
+                other
+            )))
+        }
+    };
+    let cred = StorageCredentials::Key(account.clone(), key);
+    let blob = azure_builder(account, cred).blob_client(container, name);
+    let expiry =
+        OffsetDateTime::now_utc() + time::Duration::seconds(expiration);
+    blob.shared_access_signature(permissions, expiry)
+        .and_then(|sas| blob.generate_signed_blob_url(&sas))
+        .map(|url| url.to_string())
+        .map_err(|e| azure_err(path, e))
+}
+
+/// Where a ListPages iterator is listing from
+enum ListSource {
+    S3 {
+        config: S3Config,
+        requester_pays: bool,
+        anon: bool,
+    },
+    Gcs {
+        requester_pays: bool,
+        anon: bool,
+        project: Option<String>,
+    },
+    Azure {
+        client: ClientBuilder,
+        pages: Option<BlobPages>,
+    },
+}
+
+/// Listing of a path, fetched a page at a time as it is iterated; each item
+/// is a list of file and directory info dicts. With `recursive`, lists all
+/// files below the path, else its files and directories, like ls.
+#[pyclass]
+struct ListPages {
+    source: ListSource,
+    bucket: String,
+    prefix: String,
+    recursive: bool,
+    next: Option<String>,
+    done: bool,
+}
+
+impl ListPages {
+    fn new(
+        source: ListSource, bucket: &str, key: &str, recursive: bool,
+    ) -> ListPages {
+        let prefix = match key.trim_end_matches('/') {
+            "" => String::new(),
+            k => format!("{}/", k),
+        };
+        ListPages {
+            source,
+            bucket: bucket.to_string(),
+            prefix,
+            recursive,
+            next: None,
+            done: false,
+        }
+    }
+
+    async fn fetch(&mut self) -> PyResult<Vec<HashMap<String, String>>> {
+        let (bucket, prefix) = (self.bucket.as_str(), self.prefix.as_str());
+        let delimited = !self.recursive;
+        let ((mut files, prefixes), next) = match &mut self.source {
+            ListSource::S3 { config, requester_pays, anon } => {
+                let s3_client = s3_bucket(config, bucket, *anon).await;
+                let opts = ListOpts {
+                    delimiter: delimited.then_some("/"),
+                    requester_pays: *requester_pays,
+                    anon: *anon,
+                    ..Default::default()
+                };
+                let page = s3_list_page(
+                    &s3_client,
+                    config,
+                    bucket,
+                    prefix,
+                    &opts,
+                    self.next.clone(),
+                )
+                .await?;
+                let files = page
+                    .objects
+                    .iter()
+                    .filter(|ob| !ob.key().unwrap_or("").ends_with('/'))
+                    .map(|ob| s3_file_info(bucket, ob))
+                    .collect();
+                ((files, page.prefixes), page.next)
+            }
+            ListSource::Gcs { requester_pays, anon, project } => {
+                let tok: Option<String> = match anon {
+                    true => None,
+                    false => Some(gcs_token().await?),
+                };
+                gcs_list_page(
+                    bucket,
+                    prefix,
+                    delimited,
+                    tok,
+                    project.as_deref(),
+                    *requester_pays,
+                    self.next.as_deref(),
+                )
+                .await?
+            }
+            ListSource::Azure { client, pages } => {
+                let pages = pages.get_or_insert_with(|| {
+                    azure_blob_pages(client.clone(), bucket, prefix, delimited)
+                });
+                // the pager ends after an error, so there is nothing to resume
+                let page = azure_list_page(pages, bucket, prefix)
+                    .await
+                    .inspect_err(|_| self.done = true)?;
+                match page {
+                    Some((listing, true)) => (listing, Some(String::new())),
+                    Some((listing, false)) => (listing, None),
+                    None => ((vec![], vec![]), None),
+                }
+            }
+        };
+        files.extend(prefixes.iter().map(|p| s3_dir_info(bucket, p)));
+        self.done = next.is_none();
+        self.next = next;
+        Ok(files)
+    }
+}
+
+#[pymethods]
+impl ListPages {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    /// next page, waiting for it with the GIL released
+    fn __next__(
+        mut slf: PyRefMut<'_, Self>, py: Python,
+    ) -> PyResult<Option<Vec<HashMap<String, String>>>> {
+        if slf.done {
+            return Ok(None);
+        }
+        let pages: &mut ListPages = &mut slf;
+        py.allow_threads(|| RUNTIME.block_on(pages.fetch())).map(Some)
+    }
+}
+
+/// iterate over the listing of "bucket/prefix" a page at a time
+#[pyfunction]
+#[pyo3(signature = (path, recursive=false, anon=false, requester_pays=false, **config))]
+fn s3_list_pages(
+    path: &str, recursive: bool, anon: bool, requester_pays: bool,
+    config: Option<&PyDict>,
+) -> PyResult<ListPages> {
+    let config = S3Config::from_kwargs(config)?;
+    let source = ListSource::S3 { config, requester_pays, anon };
+    let loc = s3_path(path)?;
+    let (bucket, key) = loc.parts();
+    Ok(ListPages::new(source, bucket, key, recursive))
+}
+
+/// iterate over the listing of "bucket/prefix" a page at a time
+#[pyfunction]
+#[pyo3(signature = (path, recursive=false, requester_pays=false, anon=false, project=None))]
+fn gcs_list_pages(
+    path: &str, recursive: bool, requester_pays: bool, anon: bool,
+    project: Option<String>,
+) -> ListPages {
+    let source = ListSource::Gcs { requester_pays, anon, project };
+    let (bucket, key) = path.split_once("/").unwrap_or((path, ""));
+    ListPages::new(source, bucket, key, recursive)
+}
+
+/// iterate over the listing of "container/prefix" a page at a time
+#[pyfunction]
+#[pyo3(signature = (path, account, recursive=false, anon=false, key=None))]
+fn azure_list_pages(
+    path: &str, account: String, recursive: bool, anon: bool,
+    key: Option<String>,
+) -> ListPages {
+    let cred = match (anon, key) {
+        (false, Some(key)) => StorageCredentials::Key(account.clone(), key),
+        _ => StorageCredentials::Anonymous,
+    };
+    let client = azure_builder(account, cred);
+    let (container, key) = path.split_once("/").unwrap_or((path, ""));
+    let source = ListSource::Azure { client, pages: None };
+    ListPages::new(source, container, key, recursive)
 }
 
 /// A Python module implemented in Rust.
 #[pymodule]
 fn rfsspec(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(cat_ranges, m)?)?;
+    m.add_function(wrap_pyfunction!(info_many, m)?)?;
     m.add_function(wrap_pyfunction!(get, m)?)?;
     m.add_function(wrap_pyfunction!(s3_cat_ranges, m)?)?;
     m.add_function(wrap_pyfunction!(gcs_cat_ranges, m)?)?;
     m.add_function(wrap_pyfunction!(azure_cat_ranges, m)?)?;
     m.add_function(wrap_pyfunction!(s3_info, m)?)?;
+    m.add_function(wrap_pyfunction!(s3_info_many, m)?)?;
+    m.add_function(wrap_pyfunction!(s3_du, m)?)?;
+    m.add_function(wrap_pyfunction!(s3_restore, m)?)?;
     m.add_function(wrap_pyfunction!(s3_find, m)?)?;
     m.add_function(wrap_pyfunction!(s3_ls, m)?)?;
+    m.add_function(wrap_pyfunction!(s3_glob, m)?)?;
+    m.add_function(wrap_pyfunction!(gcs_glob, m)?)?;
+    m.add_function(wrap_pyfunction!(azure_glob, m)?)?;
+    m.add_function(wrap_pyfunction!(gcs_info_many, m)?)?;
+    m.add_function(wrap_pyfunction!(azure_info_many, m)?)?;
+    m.add_function(wrap_pyfunction!(gcs_du, m)?)?;
+    m.add_function(wrap_pyfunction!(azure_du, m)?)?;
+    m.add_function(wrap_pyfunction!(s3_list_pages, m)?)?;
+    m.add_function(wrap_pyfunction!(gcs_list_pages, m)?)?;
+    m.add_function(wrap_pyfunction!(azure_list_pages, m)?)?;
+    m.add_function(wrap_pyfunction!(gcs_pipe, m)?)?;
+    m.add_function(wrap_pyfunction!(azure_pipe, m)?)?;
+    m.add_function(wrap_pyfunction!(s3_sign, m)?)?;
+    m.add_function(wrap_pyfunction!(s3_get_tags, m)?)?;
+    m.add_function(wrap_pyfunction!(s3_checksum, m)?)?;
+    m.add_function(wrap_pyfunction!(s3_select, m)?)?;
+    m.add_function(wrap_pyfunction!(s3_delete, m)?)?;
+    m.add_function(wrap_pyfunction!(s3_list_buckets, m)?)?;
+    m.add_function(wrap_pyfunction!(s3_create_bucket, m)?)?;
+    m.add_function(wrap_pyfunction!(s3_delete_bucket, m)?)?;
+    m.add_function(wrap_pyfunction!(gcs_list_buckets, m)?)?;
+    m.add_function(wrap_pyfunction!(gcs_create_bucket, m)?)?;
+    m.add_function(wrap_pyfunction!(gcs_delete_bucket, m)?)?;
+    m.add_function(wrap_pyfunction!(gcs_delete, m)?)?;
+    m.add_function(wrap_pyfunction!(azure_list_containers, m)?)?;
+    m.add_function(wrap_pyfunction!(azure_create_container, m)?)?;
+    m.add_function(wrap_pyfunction!(azure_delete_container, m)?)?;
+    m.add_function(wrap_pyfunction!(azure_delete, m)?)?;
+    m.add_function(wrap_pyfunction!(s3_put_tags, m)?)?;
+    m.add_function(wrap_pyfunction!(s3_get_retention, m)?)?;
+    m.add_function(wrap_pyfunction!(s3_put_retention, m)?)?;
+    m.add_function(wrap_pyfunction!(s3_get_legal_hold, m)?)?;
+    m.add_function(wrap_pyfunction!(s3_put_legal_hold, m)?)?;
+    m.add_function(wrap_pyfunction!(gcs_sign, m)?)?;
+    m.add_function(wrap_pyfunction!(azure_sign, m)?)?;
+    m.add_class::<ListPages>()?;
+    m.add_class::<SelectRecords>()?;
+    m.add("FileChangedError", m.py().get_type::<FileChangedError>())?;
+    m.add("ChecksumError", m.py().get_type::<ChecksumError>())?;
+    m.add("ObjectArchivedError", m.py().get_type::<ObjectArchivedError>())?;
+    m.add(
+        "PreconditionFailedError",
+        m.py().get_type::<PreconditionFailedError>(),
+    )?;
+    m.add_function(wrap_pyfunction!(s3_ls_versions, m)?)?;
+    m.add_function(wrap_pyfunction!(s3_get, m)?)?;
+    m.add_function(wrap_pyfunction!(s3_put, m)?)?;
+    m.add_function(wrap_pyfunction!(s3_copy, m)?)?;
     m.add_function(wrap_pyfunction!(s3_init_upload, m)?)?;
     m.add_function(wrap_pyfunction!(s3_upload_chunk, m)?)?;
     m.add_function(wrap_pyfunction!(s3_pipe, m)?)?;
     //m.add_function(wrap_pyfunction!(io::pybytes_from_pybytes, m)?)?;
     //m.add_function(wrap_pyfunction!(io::pybuf_from_pybuf, m)?)?;
     m.add_function(wrap_pyfunction!(s3_complete_upload, m)?)?;
+    m.add_function(wrap_pyfunction!(s3_abort_upload, m)?)?;
+    m.add_function(wrap_pyfunction!(s3_list_uploads, m)?)?;
+    m.add_function(wrap_pyfunction!(s3_list_parts, m)?)?;
+    m.add_function(wrap_pyfunction!(s3_abort_stale_uploads, m)?)?;
     m.add_class::<io::ArcVec>()?;
     Ok(())
 }
@@ -0,0 +1,253 @@
+/// The bucket and key of an S3 path. The bucket may be an access point (or
+/// Outposts access point) ARN, which the SDK takes in place of a bucket name
+/// and sends to the access point's endpoint.
+#[derive(Clone, Debug, PartialEq)]
+pub struct S3Location {
+    pub bucket: String,
+    pub key: String,
+}
+
+impl S3Location {
+    /// From any of
+    /// - "bucket/key", optionally after "s3://" (or "s3a://")
+    /// - a virtual-hosted URL, "https://bucket.s3.region.amazonaws.com/key"
+    /// - a path-style URL, "https://s3.region.amazonaws.com/bucket/key"
+    /// - an access point ARN, "arn:aws:s3:region:account:accesspoint/name",
+    ///   or Outposts access point ARN,
+    ///   "arn:aws:s3-outposts:region:account:outpost/id/accesspoint/name",
+    ///   followed by "/key"
+    ///
+    /// The key may be empty, for a whole bucket.
+    pub fn parse(path: &str) -> Result<S3Location, String> {
+        let path = ["s3://", "s3a://"]
+            .iter()
+            .find_map(|scheme| path.strip_prefix(scheme))
+            .unwrap_or(path);
+        if path.starts_with("arn:") {
+            return parse_arn(path);
+        }
+        if let Some(url) = ["https://", "http://"]
+            .iter()
+            .find_map(|scheme| path.strip_prefix(scheme))
+        {
+            return parse_url(url);
+        }
+        let (bucket, key) = path.split_once('/').unwrap_or((path, ""));
+        check_bucket(bucket)?;
+        Ok(S3Location { bucket: bucket.to_string(), key: key.to_string() })
+    }
+
+    pub fn parts(&self) -> (&str, &str) {
+        (&self.bucket, &self.key)
+    }
+}
+
+/// The region of a bucket given as an access point ARN
+pub fn arn_region(bucket: &str) -> Option<&str> {
+    // arn:partition:service:region:...
+    let region = bucket.strip_prefix("arn:")?.split(':').nth(2)?;
+    Some(region).filter(|r| !r.is_empty())
+}
+
+/// Bucket names as S3 allows, if loosely: legacy buckets and other stores
+/// may have upper case or underscores
+fn check_bucket(bucket: &str) -> Result<(), String> {
+    if bucket.is_empty() {
+        return Err("no bucket".to_string());
+    }
+    let valid = |c: char| c.is_ascii_alphanumeric() || "-._".contains(c);
+    if !bucket.chars().all(valid) {
+        return Err(format!("invalid bucket name \"{}\"", bucket));
+    }
+    Ok(())
+}
+
+fn parse_arn(path: &str) -> Result<S3Location, String> {
+    // arn:partition:service:region:account:resource
+    let fields: Vec<&str> = path.splitn(6, ':').collect();
+    let [_, partition, service, region, account, resource] = fields[..] else {
+        return Err("incomplete ARN".to_string());
+    };
+    if partition.is_empty() || account.is_empty() {
+        return Err("ARN without partition or account".to_string());
+    }
+    if region.is_empty() {
+        return Err("multi-region access points are not supported".to_string());
+    }
+    // the resource's leading segments name the access point
+    let expected: &[&str] = match service {
+        "s3" => &["accesspoint", ""],
+        "s3-outposts" => &["outpost", "", "accesspoint", ""],
+        _ => return Err(format!("not an S3 ARN (service \"{}\")", service)),
+    };
+    let segments: Vec<&str> =
+        resource.splitn(expected.len() + 1, '/').collect();
+    let names_access_point = segments.len() >= expected.len()
+        && segments.iter().zip(expected).all(|(seg, exp)| match *exp {
+            "" => !seg.is_empty(),
+            exp => *seg == exp,
+        });
+    if !names_access_point {
+        return Err(format!(
+            "ARN resource \"{}\" is not {}",
+            resource,
+            expected
+                .iter()
+                .map(|e| if e.is_empty() { "<name>" } else { e })
+                .collect::<Vec<_>>()
+                .join("/")
+        ));
+    }
+    let bucket = format!(
+        "arn:{}:{}:{}:{}:{}",
+        partition,
+        service,
+        region,
+        account,
+        segments[..expected.len()].join("/")
+    );
+    let key = segments.get(expected.len()).copied().unwrap_or("");
+    Ok(S3Location { bucket, key: key.to_string() })
+}
+
+/// From a URL, after the scheme, for an AWS S3 endpoint
+fn parse_url(url: &str) -> Result<S3Location, String> {
+    let (host, path) = url.split_once('/').unwrap_or((url, ""));
+    let host = host.split(':').next().unwrap_or(host);
+    // query (e.g., a presigned URL's) and fragment are not part of the key
+    let path = path.split(['?', '#']).next().unwrap_or(path);
+    let path = urlencoding::decode(path)
+        .map_err(|_| "URL path is not UTF-8".to_string())?;
+    let base = [".amazonaws.com", ".amazonaws.com.cn"]
+        .iter()
+        .find_map(|domain| host.strip_suffix(domain))
+        .ok_or_else(|| format!("\"{}\" is not an S3 endpoint", host))?;
+    let labels: Vec<&str> = base.split('.').collect();
+    if labels.iter().any(|l| l.starts_with("s3-accesspoint")) {
+        return Err("access point URLs are not supported; use the ARN".into());
+    }
+    // "s3", or "s3-region" in older endpoints
+    let service = labels
+        .iter()
+        .rposition(|l| *l == "s3" || l.starts_with("s3-"))
+        .ok_or_else(|| format!("\"{}\" is not an S3 endpoint", host))?;
+    let (bucket, key) = match service {
+        // path-style
+        0 => {
+            let (bucket, key) = path.split_once('/').unwrap_or((&path, ""));
+            (bucket.to_string(), key.to_string())
+        }
+        i => (labels[..i].join("."), path.into_owned()),
+    };
+    check_bucket(&bucket)?;
+    Ok(S3Location { bucket, key })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parts(path: &str) -> (String, String) {
+        let loc = S3Location::parse(path).unwrap();
+        (loc.bucket, loc.key)
+    }
+
+    fn pair(bucket: &str, key: &str) -> (String, String) {
+        (bucket.to_string(), key.to_string())
+    }
+
+    #[test]
+    fn bucket_and_key() {
+        assert_eq!(parts("bucket/a/b"), pair("bucket", "a/b"));
+        assert_eq!(parts("s3://bucket/a/b"), pair("bucket", "a/b"));
+        assert_eq!(parts("s3a://bucket/a"), pair("bucket", "a"));
+        assert_eq!(parts("s3://bucket"), pair("bucket", ""));
+        assert_eq!(
+            parts("my.dotted.bucket/key"),
+            pair("my.dotted.bucket", "key")
+        );
+        assert!(S3Location::parse("s3://").is_err());
+        assert!(S3Location::parse("bad bucket/key").is_err());
+    }
+
+    #[test]
+    fn virtual_hosted() {
+        assert_eq!(
+            parts("https://bucket.s3.us-west-2.amazonaws.com/a/b"),
+            pair("bucket", "a/b")
+        );
+        assert_eq!(
+            parts("https://my.dotted.bucket.s3.eu-west-1.amazonaws.com/key"),
+            pair("my.dotted.bucket", "key")
+        );
+        // region-less and legacy "s3-region" hosts
+        assert_eq!(
+            parts("https://bucket.s3.amazonaws.com/key"),
+            pair("bucket", "key")
+        );
+        assert_eq!(
+            parts("https://bucket.s3-us-west-2.amazonaws.com/key"),
+            pair("bucket", "key")
+        );
+        assert_eq!(
+            parts("https://bucket.s3.cn-north-1.amazonaws.com.cn/key?x=1"),
+            pair("bucket", "key")
+        );
+        assert_eq!(
+            parts("https://bucket.s3.amazonaws.com/a%20b"),
+            pair("bucket", "a b")
+        );
+    }
+
+    #[test]
+    fn path_style() {
+        assert_eq!(
+            parts("https://s3.us-west-2.amazonaws.com/bucket/a/b"),
+            pair("bucket", "a/b")
+        );
+        assert_eq!(
+            parts("https://s3.amazonaws.com/my.dotted.bucket/key"),
+            pair("my.dotted.bucket", "key")
+        );
+        assert_eq!(
+            parts("http://s3.amazonaws.com:443/bucket"),
+            pair("bucket", "")
+        );
+        assert!(S3Location::parse("https://s3.amazonaws.com/").is_err());
+        assert!(S3Location::parse("https://example.com/bucket/key").is_err());
+        assert!(S3Location::parse(
+            "https://ap-111122223333.s3-accesspoint.us-west-2.amazonaws.com/k"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn access_point_arns() {
+        let arn = "arn:aws:s3:us-west-2:123456789012:accesspoint/my-ap";
+        assert_eq!(parts(&format!("{}/a/b", arn)), pair(arn, "a/b"));
+        assert_eq!(parts(&format!("s3://{}", arn)), pair(arn, ""));
+        assert_eq!(arn_region(arn), Some("us-west-2"));
+
+        let outpost = "arn:aws:s3-outposts:us-east-1:123456789012:\
+                       outpost/op-01234567890123456/accesspoint/my-ap";
+        assert_eq!(parts(&format!("{}/key", outpost)), pair(outpost, "key"));
+        assert_eq!(arn_region(outpost), Some("us-east-1"));
+        assert_eq!(arn_region("bucket"), None);
+    }
+
+    #[test]
+    fn malformed_arns() {
+        for arn in [
+            "arn:aws:s3:us-west-2:123456789012",
+            "arn:aws:s3::123456789012:accesspoint/my-ap",
+            "arn:aws:s3:us-west-2::accesspoint/my-ap",
+            "arn::s3:us-west-2:123456789012:accesspoint/my-ap",
+            "arn:aws:s3:us-west-2:123456789012:bucket/my-bucket",
+            "arn:aws:s3:us-west-2:123456789012:accesspoint/",
+            "arn:aws:sqs:us-west-2:123456789012:accesspoint/my-ap",
+            "arn:aws:s3-outposts:us-east-1:123456789012:outpost/op-1/my-ap",
+        ] {
+            assert!(S3Location::parse(arn).is_err(), "{}", arn);
+        }
+    }
+}
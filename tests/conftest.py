@@ -1,6 +1,9 @@
 import contextlib
 import json
+import os
+import socket
 import threading
+import uuid
 from collections import ChainMap
 from http.server import BaseHTTPRequestHandler, HTTPServer
 
@@ -144,3 +147,44 @@ def serve():
 def server():
     with serve() as s:
         yield s
+
+
+@pytest.fixture()
+def gcs():
+    """A fresh bucket on a GCS emulator, such as fake-gcs-server, found by
+    STORAGE_EMULATOR_HOST as for Google's own clients"""
+    if not os.environ.get("STORAGE_EMULATOR_HOST"):
+        pytest.skip("needs a GCS emulator at STORAGE_EMULATOR_HOST")
+    import rfsspec
+
+    fs = rfsspec.RustyGCSFileSystem(anon=True, project="test")
+    bucket = "rfsspec-test-%s" % uuid.uuid4().hex[:8]
+    fs.mkdir(bucket)
+    yield fs, bucket
+    for path in fs.find(bucket):
+        fs.rm_file(path)
+    fs.rmdir(bucket)
+
+
+# Azurite's well-known development account
+AZURITE_ACCOUNT = "devstoreaccount1"
+AZURITE_KEY = ("Eby8vdM02xNOcqFlqUwJPLlmEtlCDXJ1OUzFT50uSRZ6IFsuFq2UVErCz4I6tq/"
+               "K1SZFPTOtr/KBHBeksoGMGw==")
+
+
+@pytest.fixture()
+def azure():
+    """A fresh container on Azurite, listening on localhost:10000"""
+    try:
+        socket.create_connection(("127.0.0.1", 10000), timeout=1).close()
+    except OSError:
+        pytest.skip("needs Azurite on localhost:10000")
+    import rfsspec
+
+    fs = rfsspec.RustyAzureFileSystem(account=AZURITE_ACCOUNT, key=AZURITE_KEY)
+    container = "rfsspec-test-%s" % uuid.uuid4().hex[:8]
+    fs.mkdir(container)
+    yield fs, container
+    for path in fs.find(container):
+        fs.rm_file(path)
+    fs.rmdir(container)
@@ -0,0 +1,49 @@
+import pytest
+
+import rfsspec
+
+
+def test_read_changed_file(azure):
+    fs, container = azure
+    fn = f"{container}/changing"
+    fs.pipe(fn, b"a" * 100)
+
+    with fs.open(fn, "rb", block_size=10, cache_type="none") as f:
+        assert f.read(10) == b"a" * 10
+        fs.pipe(fn, b"b" * 100)
+        with pytest.raises(rfsspec.FileChangedError):
+            f.read(10)
+    assert fs.cat(fn) == b"b" * 100
+
+
+def test_sign(azure):
+    import urllib.request
+
+    fs, container = azure
+    fn = f"{container}/signed"
+    fs.pipe(fn, b"data")
+    url = fs.sign(fn, expiration=60)
+    with urllib.request.urlopen(url) as r:
+        assert r.read() == b"data"
+
+    url = fs.sign(fn, method="PUT")
+    req = urllib.request.Request(url, data=b"new", method="PUT",
+                                 headers={"x-ms-blob-type": "BlockBlob"})
+    urllib.request.urlopen(req).close()
+    assert fs.cat(fn) == b"new"
+
+    with pytest.raises(ValueError):
+        fs.sign(fn, method="POST")
+
+
+def test_containers(azure):
+    fs, container = azure
+    # the container exists, so only the directory is made
+    fs.makedirs(f"{container}/sub/dir", exist_ok=True)
+    with pytest.raises(FileExistsError):
+        fs.mkdir(container)
+
+    fs.pipe(f"{container}/afile", b"data")
+    with pytest.raises(FileExistsError):
+        fs.rmdir(container)
+    assert fs.cat(f"{container}/afile") == b"data"
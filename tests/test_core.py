@@ -61,3 +61,13 @@ def test_get_one(server, tmpdir):
         out = f.read()
     assert len(out) == len(data)
     assert out == data
+
+
+def test_info_many(server):
+    fs = rfsspec.RustyHTTPFileSystem()
+    urls = [server + "/index/realfile", server + "/index/missing", server + "/index/otherfile"]
+    out = fs.info_many(urls, headers={"head_ok": "1", "give_length": "1"})
+    assert out[1] is None
+    assert [o["size"] for o in (out[0], out[2])] == [len(data)] * 2
+    # HEAD not allowed, so GET
+    assert fs.exists_many(urls) == [True, False, True]
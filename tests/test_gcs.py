@@ -0,0 +1,50 @@
+import pytest
+
+import rfsspec
+
+
+def test_read_changed_file(gcs):
+    fs, bucket = gcs
+    fn = f"{bucket}/changing"
+    fs.pipe(fn, b"a" * 100)
+
+    with fs.open(fn, "rb", block_size=10, cache_type="none") as f:
+        assert f.read(10) == b"a" * 10
+        fs.pipe(fn, b"b" * 100)
+        with pytest.raises(rfsspec.FileChangedError):
+            f.read(10)
+    assert fs.cat(fn) == b"b" * 100
+
+
+def test_sign(gcs, tmp_path):
+    import json
+    import os
+    import urllib.request
+    rsa = pytest.importorskip("cryptography.hazmat.primitives.asymmetric.rsa")
+    from cryptography.hazmat.primitives import serialization
+
+    key = rsa.generate_private_key(public_exponent=65537, key_size=2048)
+    pem = key.private_bytes(serialization.Encoding.PEM, serialization.PrivateFormat.PKCS8,
+                            serialization.NoEncryption()).decode()
+    creds = tmp_path / "key.json"
+    creds.write_text(json.dumps({"type": "service_account", "private_key": pem,
+                                 "client_email": "signer@test.iam.gserviceaccount.com"}))
+
+    fs, bucket = gcs
+    fn = f"{bucket}/signed"
+    fs.pipe(fn, b"data")
+    url = fs.sign(fn, expiration=60, credentials=str(creds))
+    # signed for the emulator, not storage.googleapis.com
+    host = os.environ["STORAGE_EMULATOR_HOST"].split("://")[-1].rstrip("/")
+    assert url.split("://")[1].startswith(f"{host}/{fn}?")
+    assert "X-Goog-Signature=" in url
+    with urllib.request.urlopen(url) as r:
+        assert r.read() == b"data"
+
+
+def test_buckets(gcs):
+    fs, bucket = gcs
+    # the bucket exists, so only the directory is made
+    fs.makedirs(f"{bucket}/sub/dir", exist_ok=True)
+    with pytest.raises(FileExistsError):
+        fs.mkdir(bucket)
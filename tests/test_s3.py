@@ -1,3 +1,6 @@
+import gc
+
+import pytest
 from s3fs.tests.test_s3fs import s3, s3_base, get_boto3_client, endpoint_uri, test_bucket_name
 
 import rfsspec
@@ -40,3 +43,494 @@ def test_multipart_roundtrip(s3):
 
     out = fs.cat(fn)
     assert out == b"0" * (bs * 2)
+
+
+def test_info_dir_missing(s3):
+    fs = rfsspec.RustyS3FileSystem(endpoint_url=endpoint_uri)
+    fs.pipe(f"{test_bucket_name}/deep/inner/afile", b"data")
+
+    assert fs.info(f"{test_bucket_name}/deep/inner/afile")["type"] == "file"
+    assert fs.info(f"{test_bucket_name}/deep/inner")["type"] == "directory"
+    assert fs.info(f"{test_bucket_name}/deep/")["type"] == "directory"
+    assert fs.isdir(f"{test_bucket_name}/deep")
+    assert not fs.isdir(f"{test_bucket_name}/deep/inner/afile")
+    with pytest.raises(FileNotFoundError):
+        fs.info(f"{test_bucket_name}/nothing/here")
+    assert not fs.isdir(f"{test_bucket_name}/nothing")
+
+
+def test_versions(s3):
+    versioned = "test-versioned"
+    client = get_boto3_client()
+    client.create_bucket(Bucket=versioned)
+    client.put_bucket_versioning(Bucket=versioned, VersioningConfiguration={"Status": "Enabled"})
+    fs = rfsspec.RustyS3FileSystem(endpoint_url=endpoint_uri, version_aware=True)
+    fn = f"{versioned}/afile"
+    v1 = fs.pipe(fn, b"first")[fn]["VersionId"]
+    with fs.open(fn, "wb") as f:
+        f.write(b"second")
+    v2 = f.version_id
+    assert v1 != v2
+
+    assert fs.cat(fn) == b"second"
+    assert fs.cat(f"{fn}?versionId={v1}") == b"first"
+    assert fs.info(fn)["VersionId"] == v2
+    with fs.open(fn, version_id=v1) as f:
+        assert f.read() == b"first"
+
+    versions = fs.object_version_info(fn)
+    assert {v["VersionId"] for v in versions} == {v1, v2}
+    assert [v["IsLatest"] for v in versions if v["VersionId"] == v2] == [True]
+
+
+
+def test_get_recursive_cat_starts(s3, tmpdir):
+    fs = rfsspec.RustyS3FileSystem(endpoint_url=endpoint_uri)
+    fs.pipe({f"{test_bucket_name}/tree/a": b"aaaa", f"{test_bucket_name}/tree/sub/b": b"bbbb"})
+
+    fs.get(f"{test_bucket_name}/tree", str(tmpdir / "out"), recursive=True)
+    assert (tmpdir / "out" / "sub" / "b").read_binary() == b"bbbb"
+
+    paths = [f"{test_bucket_name}/tree/a", f"{test_bucket_name}/tree/sub/b"]
+    out = fs.cat(paths, start=[1, 2])
+    assert list(out.values()) == [fs.cat_file(paths[0], start=1), fs.cat_file(paths[1], start=2)]
+
+
+def test_sse_put_copy(s3, tmpdir):
+    fs = rfsspec.RustyS3FileSystem(endpoint_url=endpoint_uri, sse="AES256")
+    lpath = f"{tmpdir}/afile"
+    with open(lpath, "wb") as f:
+        f.write(b"secret")
+    fn = f"{test_bucket_name}/encrypted"
+    fs.put_file(lpath, fn)
+    assert fs.info(fn)["ServerSideEncryption"] == "AES256"
+    fs.cp_file(fn, fn + "2")
+    assert fs.info(fn + "2")["ServerSideEncryption"] == "AES256"
+    assert fs.cat(fn + "2") == b"secret"
+
+    fs = rfsspec.RustyS3FileSystem(endpoint_url=endpoint_uri, sse_customer_key=b"too short")
+    with pytest.raises(ValueError):
+        fs.cat(fn)
+
+
+def test_multipart_abort(s3):
+    fs = rfsspec.RustyS3FileSystem(endpoint_url=endpoint_uri)
+    fn = f"{test_bucket_name}/aborted"
+    bs = 5 * 2**20
+
+    with pytest.raises(ValueError):
+        with fs.open(fn, mode="wb", block_size=bs) as f:
+            f.write(b"0" * (bs + 1))
+            assert f.mpu
+            assert [u["UploadId"] for u in fs.list_multipart_uploads(fn)] == [f.mpu]
+            assert [p["PartNumber"] for p in fs.list_parts(fn, f.mpu)] == [1]
+            raise ValueError
+    assert not fs.list_multipart_uploads(fn)
+    assert not fs.exists(fn)
+
+    with fs.open(fn, mode="wb", block_size=bs) as f:
+        f.write(b"0" * (bs + 1))
+        assert fs.clear_multipart_uploads(test_bucket_name, older_than=3600) == ()
+        assert fs.clear_multipart_uploads(test_bucket_name)
+        f.mpu = None
+        f.discard()
+    assert not fs.list_multipart_uploads(test_bucket_name)
+
+    f = fs.open(fn, mode="wb", block_size=bs)
+    f.write(b"0" * (bs + 1))
+    assert fs.list_multipart_uploads(fn)
+    del f
+    gc.collect()
+    assert not fs.list_multipart_uploads(fn)
+    assert not fs.exists(fn)
+
+
+def test_explicit_credentials(s3):
+    fs = rfsspec.RustyS3FileSystem(endpoint_url=endpoint_uri, key="foo", secret="bar", token="baz")
+    fn = f"{test_bucket_name}/creds"
+    fs.pipe(fn, b"data")
+    assert fs.cat(fn) == b"data"
+
+    with pytest.raises(TypeError):
+        rfsspec.rfsspec.s3_info(fn, endpoint_url=endpoint_uri, not_an_option=True)
+    with pytest.raises(ValueError):
+        rfsspec.RustyS3FileSystem(endpoint_url=endpoint_uri, key="foo").cat(fn)
+
+
+def test_client_options(s3):
+    fs = rfsspec.RustyS3FileSystem(endpoint_url=endpoint_uri, force_path_style=True,
+                                   list_version=1, connect_timeout=5, max_attempts=2,
+                                   disable_payload_signing=True)
+    for i in range(3):
+        fs.pipe(f"{test_bucket_name}/opts/file{i}", b"data")
+    with fs.open(f"{test_bucket_name}/opts/big", "wb", block_size=5 * 2**20) as f:
+        f.write(b"0" * (6 * 2**20))
+
+    assert fs.cat(f"{test_bucket_name}/opts/file0") == b"data"
+    assert len(fs.find(f"{test_bucket_name}/opts")) == 4
+    assert len(fs.ls(f"{test_bucket_name}/opts")) == 4
+    assert fs.isdir(f"{test_bucket_name}/opts")
+
+
+def test_find_maxdepth_withdirs(s3):
+    fs = rfsspec.RustyS3FileSystem(endpoint_url=endpoint_uri)
+    root = f"{test_bucket_name}/tree"
+    for key in ["a", "b/c", "b/d/e", "b/d/f"]:
+        fs.pipe(f"{root}/{key}", b"data")
+
+    assert fs.find(root) == [f"{root}/a", f"{root}/b/c", f"{root}/b/d/e", f"{root}/b/d/f"]
+    assert fs.find(root, withdirs=True) == [
+        f"{root}/a", f"{root}/b", f"{root}/b/c", f"{root}/b/d", f"{root}/b/d/e", f"{root}/b/d/f"]
+    assert fs.find(root, maxdepth=1) == [f"{root}/a"]
+    assert fs.find(root, maxdepth=2, withdirs=True) == [
+        f"{root}/a", f"{root}/b", f"{root}/b/c", f"{root}/b/d"]
+    assert fs.find(root, start_after=f"{root}/b/c") == [f"{root}/b/d/e", f"{root}/b/d/f"]
+    assert fs.find(f"{root}/a") == [f"{root}/a"]
+
+    walked = list(fs.walk(root))
+    assert walked[0] == (root, ["b"], ["a"])
+    assert (f"{root}/b/d", [], ["e", "f"]) in walked
+    assert list(fs.walk(root, maxdepth=1)) == [(root, ["b"], ["a"])]
+    pruned = []
+    for dirpath, dirs, _ in fs.walk(root):
+        pruned.append(dirpath)
+        dirs.clear()
+    assert pruned == [root]
+
+    with pytest.raises(FileNotFoundError):
+        fs.find("no-such-bucket-rfsspec/x")
+
+
+def test_glob(s3):
+    fs = rfsspec.RustyS3FileSystem(endpoint_url=endpoint_uri)
+    root = f"{test_bucket_name}/globs"
+    for key in ["a.csv", "b.txt", "sub/c.csv", "sub/deep/d.csv", "sub2/e.csv"]:
+        fs.pipe(f"{root}/{key}", b"data")
+
+    assert fs.glob(f"{root}/*.csv") == [f"{root}/a.csv"]
+    assert fs.glob(f"{root}/?.*") == [f"{root}/a.csv", f"{root}/b.txt"]
+    assert fs.glob(f"{root}/[ab].csv") == [f"{root}/a.csv"]
+    assert fs.glob(f"{root}/sub*") == [f"{root}/sub", f"{root}/sub2"]
+    assert fs.glob(f"{root}/*/*.csv") == [f"{root}/sub/c.csv", f"{root}/sub2/e.csv"]
+    assert fs.glob(f"{root}/**/*.csv") == [
+        f"{root}/a.csv", f"{root}/sub/c.csv", f"{root}/sub/deep/d.csv", f"{root}/sub2/e.csv"]
+    assert fs.glob(f"{root}/b.txt") == [f"{root}/b.txt"]
+    assert fs.glob(f"{root}/*.parquet") == []
+    assert fs.glob(f"{root}/**/*.csv", maxdepth=2) == [
+        f"{root}/a.csv", f"{root}/sub/c.csv", f"{root}/sub2/e.csv"]
+    assert fs.glob(f"{root}/s*", maxdepth=1) == [f"{root}/sub", f"{root}/sub2"]
+
+
+@pytest.mark.parametrize("name,keys", [
+    ("dirs", ["a/1", "b/2", "b/3", "c", "d/e/4"]),  # partition by directory
+    ("split", ["x/1", "x/A", "x/b", "x/~z", "x/y/2"]),  # one directory, split by key
+])
+def test_find_parallel(s3, name, keys):
+    fs = rfsspec.RustyS3FileSystem(endpoint_url=endpoint_uri, list_parallelism=4)
+    root = f"{test_bucket_name}/par-{name}"
+    for key in keys:
+        fs.pipe(f"{root}/{key}", b"data")
+
+    assert fs.find(root) == sorted(f"{root}/{k}" for k in keys)
+
+
+def test_ls_iter(s3):
+    fs = rfsspec.RustyS3FileSystem(endpoint_url=endpoint_uri)
+    root = f"{test_bucket_name}/iter"
+    for key in ["a", "b", "sub/c"]:
+        fs.pipe(f"{root}/{key}", b"data")
+
+    it = fs.ls_iter(root)
+    assert next(it)["name"].startswith(root)
+    assert sorted(o["name"] for o in fs.ls_iter(root)) == [
+        f"{root}/a", f"{root}/b", f"{root}/sub"]
+    assert sorted(o["name"] for o in fs.ls_iter(root, recursive=True)) == [
+        f"{root}/a", f"{root}/b", f"{root}/sub/c"]
+
+
+def test_read_changed_file(s3):
+    fs = rfsspec.RustyS3FileSystem(endpoint_url=endpoint_uri)
+    fn = f"{test_bucket_name}/changing"
+    fs.pipe(fn, b"a" * 100)
+
+    with fs.open(fn, "rb", block_size=10, cache_type="none") as f:
+        assert f.read(10) == b"a" * 10
+        fs.pipe(fn, b"b" * 100)
+        with pytest.raises(rfsspec.FileChangedError):
+            f.read(10)
+    assert fs.cat(fn) == b"b" * 100
+
+
+def test_conditional_write(s3):
+    fs = rfsspec.RustyS3FileSystem(endpoint_url=endpoint_uri)
+    fn = f"{test_bucket_name}/conditional"
+    etag = fs.pipe(fn, b"first", if_none_match="*")[fn]["ETag"]
+    with pytest.raises(rfsspec.PreconditionFailedError):
+        fs.pipe(fn, b"second", if_none_match="*")
+    with pytest.raises(rfsspec.PreconditionFailedError):
+        fs.pipe(fn, b"second", if_match='"not-the-etag"')
+    fs.pipe(fn, b"second", if_match=etag)
+    assert fs.cat(fn) == b"second"
+
+    with pytest.raises(ValueError):
+        fs.pipe(fn, b"third", if_none_match="something")
+
+
+def test_sign(s3):
+    import urllib.request
+
+    fs = rfsspec.RustyS3FileSystem(endpoint_url=endpoint_uri)
+    fn = f"{test_bucket_name}/signed"
+    fs.pipe(fn, b"data")
+    url = fs.sign(fn, expiration=60)
+    with urllib.request.urlopen(url) as r:
+        assert r.read() == b"data"
+
+    url = fs.sign(fn, method="PUT")
+    urllib.request.urlopen(urllib.request.Request(url, data=b"new", method="PUT")).close()
+    assert fs.cat(fn) == b"new"
+
+    with pytest.raises(ValueError):
+        fs.sign(fn, method="POST")
+
+
+def test_write_options_and_tags(s3):
+    fs = rfsspec.RustyS3FileSystem(endpoint_url=endpoint_uri)
+    client = get_boto3_client()
+    fn = f"{test_bucket_name}/described.json"
+    fs.pipe(fn, b"{}", metadata={"origin": "test"}, cache_control="max-age=60",
+            storage_class="STANDARD_IA")
+    head = client.head_object(Bucket=test_bucket_name, Key="described.json")
+    assert head["ContentType"] == "application/json"
+    assert head["Metadata"] == {"origin": "test"}
+    assert head["CacheControl"] == "max-age=60"
+    assert head["StorageClass"] == "STANDARD_IA"
+
+    fn2 = f"{test_bucket_name}/described.bin"
+    with fs.open(fn2, "wb", content_type="text/plain", block_size=5 * 2**20) as f:
+        f.write(b"0" * (6 * 2**20))
+    head = client.head_object(Bucket=test_bucket_name, Key="described.bin")
+    assert head["ContentType"] == "text/plain"
+
+    fs.put_tags(fn, {"a": "1"})
+    fs.put_tags(fn, {"b": "2"}, mode="m")
+    assert fs.get_tags(fn) == {"a": "1", "b": "2"}
+    fs.put_tags(fn, {"c": "3"})
+    assert fs.get_tags(fn) == {"c": "3"}
+
+
+def test_pipe_many(s3):
+    fs = rfsspec.RustyS3FileSystem(endpoint_url=endpoint_uri)
+    paths = [f"{test_bucket_name}/many/{i}" for i in range(50)]
+    out = fs.pipe(((p, str(i).encode()) for i, p in enumerate(paths)), concurrency=4)
+    assert list(out) == paths
+    assert all("ETag" in o for o in out.values())
+    assert fs.cat(paths[7]) == b"7"
+
+    fn = f"{test_bucket_name}/many/0"
+    data = {fn: b"again", "nobucket": b"x", f"{test_bucket_name}/many/new": b"new"}
+    out = fs.pipe(data, if_none_match="*", raise_on_error=False)
+    assert list(out) == list(data)
+    assert isinstance(out[fn], rfsspec.PreconditionFailedError)
+    assert isinstance(out["nobucket"], ValueError)
+    assert "ETag" in out[f"{test_bucket_name}/many/new"]
+
+    with pytest.raises(ValueError):
+        fs.pipe(data)
+
+
+@pytest.mark.parametrize("kwargs", [{"anon": True}, {"requester_pays": True}])
+def test_write_path_options(s3, kwargs):
+    fs = rfsspec.RustyS3FileSystem(endpoint_url=endpoint_uri, **kwargs)
+    fn = f"{test_bucket_name}/write_opts/one"
+    fs.pipe(fn, b"data")
+    fn2 = f"{test_bucket_name}/write_opts/multi"
+    with fs.open(fn2, "wb", block_size=5 * 2**20) as f:
+        f.write(b"0" * (6 * 2**20))
+    fs.cp_file(fn, f"{test_bucket_name}/write_opts/copy")
+
+    fs = rfsspec.RustyS3FileSystem(endpoint_url=endpoint_uri)
+    assert fs.cat(fn) == b"data"
+    assert fs.cat(f"{test_bucket_name}/write_opts/copy") == b"data"
+    assert fs.info(fn2)["size"] == 6 * 2**20
+
+
+def test_checksums(s3):
+    import base64
+    import hashlib
+
+    data = b"some data to check" * 1000
+    plain = rfsspec.RustyS3FileSystem(endpoint_url=endpoint_uri)
+    plain.pipe(f"{test_bucket_name}/sums/plain", data)
+    for algorithm in ["CRC32C", "SHA256", "MD5"]:
+        fs = rfsspec.RustyS3FileSystem(endpoint_url=endpoint_uri, checksum_algorithm=algorithm)
+        fn = f"{test_bucket_name}/sums/{algorithm}"
+        out = fs.pipe(fn, data)[fn]
+        assert out[f"Checksum{algorithm}"] == fs.checksum(fn, algorithm)
+        # computed by streaming vs as recorded
+        assert plain.checksum(f"{test_bucket_name}/sums/plain", algorithm) == fs.checksum(
+            fn, algorithm)
+        assert fs.cat(fn) == data
+    assert plain.checksum(f"{test_bucket_name}/sums/SHA256", "SHA256") == base64.b64encode(
+        hashlib.sha256(data).digest()).decode()
+    assert plain.checksum(f"{test_bucket_name}/sums/plain", "MD5") == base64.b64encode(
+        hashlib.md5(data).digest()).decode()
+
+    fs = rfsspec.RustyS3FileSystem(endpoint_url=endpoint_uri, checksum_algorithm="CRC32C")
+    fn = f"{test_bucket_name}/sums/multi"
+    with fs.open(fn, "wb", block_size=5 * 2**20) as f:
+        f.write(b"0" * (6 * 2**20))
+    assert fs.cat(fn) == b"0" * (6 * 2**20)
+
+    with pytest.raises(ValueError):
+        plain.checksum(fn, "CRC64")
+
+
+def test_select(s3):
+    fs = rfsspec.RustyS3FileSystem(endpoint_url=endpoint_uri)
+    fn = f"{test_bucket_name}/select.csv"
+    fs.pipe(fn, b"name,value\na,1\nb,2\nc,3\n")
+    out = fs.select(fn, "SELECT s.name FROM S3Object s WHERE s.value > '1'",
+                    output_format="csv")
+    assert out.split() == [b"b", b"c"]
+    blocks = fs.select(fn, "SELECT * FROM S3Object s", iterator=True)
+    assert b"".join(blocks).count(b"name") == 3
+
+    with pytest.raises(ValueError):
+        fs.select(fn, "SELECT * FROM S3Object", input_format="xml")
+
+
+def test_buckets_and_dir_markers(s3):
+    fs = rfsspec.RustyS3FileSystem(endpoint_url=endpoint_uri)
+    fs.mkdir("rusty-new-bucket", region="eu-west-1")
+    assert "rusty-new-bucket" in fs.ls("", detail=False)
+    with pytest.raises(FileExistsError):
+        fs.mkdir("rusty-new-bucket")
+    fs.makedirs("rusty-new-bucket", exist_ok=True)
+
+    # without markers, an empty directory does not exist
+    fs.mkdir("rusty-new-bucket/empty")
+    assert not fs.exists("rusty-new-bucket/empty")
+
+    marked = rfsspec.RustyS3FileSystem(endpoint_url=endpoint_uri, dir_markers=True)
+    marked.mkdir("rusty-new-bucket/empty")
+    assert marked.isdir("rusty-new-bucket/empty")
+    marked.rmdir("rusty-new-bucket/empty")
+    assert not marked.exists("rusty-new-bucket/empty")
+
+    fs.pipe("rusty-new-bucket/afile", b"data")
+    with pytest.raises(FileExistsError):
+        fs.rmdir("rusty-new-bucket")
+    fs.rm_file("rusty-new-bucket/afile")
+    fs.rmdir("rusty-new-bucket")
+    assert "rusty-new-bucket" not in fs.ls("", detail=False)
+
+
+def test_info_many(s3):
+    fs = rfsspec.RustyS3FileSystem(endpoint_url=endpoint_uri)
+    paths = [f"{test_bucket_name}/many/{i}" for i in range(50)]
+    fs.pipe({p: str(i).encode() for i, p in enumerate(paths) if i % 2})
+
+    out = fs.info_many(paths + [f"{test_bucket_name}/many"], concurrency=8)
+    assert [o is None for o in out] == [i % 2 == 0 for i in range(50)] + [True]
+    assert [o["name"] for o in out[1:50:2]] == paths[1::2]
+    assert out[1]["size"] == 1
+    assert fs.exists_many(paths[:4]) == [False, True, False, True]
+    assert fs.info_many([]) == []
+
+
+@pytest.mark.parametrize("list_parallelism", [1, 4])
+def test_du(s3, list_parallelism):
+    fs = rfsspec.RustyS3FileSystem(endpoint_url=endpoint_uri,
+                                   list_parallelism=list_parallelism)
+    root = f"{test_bucket_name}/du"
+    fs.pipe({f"{root}/a": b"1", f"{root}/sub/b": b"22", f"{root}/sub/deep/c": b"333",
+             f"{root}/other/d": b"4444"})
+
+    assert fs.du(root) == 10
+    assert fs.du(root + "/") == 10
+    assert fs.du(root, maxdepth=2) == 7
+    assert fs.du(root, total=False) == {
+        root: 10, f"{root}/sub": 5, f"{root}/sub/deep": 3, f"{root}/other": 4}
+    assert fs.du(root, total=False, maxdepth=1) == {root: 1}
+    assert fs.du(f"{test_bucket_name}/nothing") == 0
+    # a file is its own usage
+    assert fs.du(f"{root}/sub/b") == 2
+    assert fs.du(f"{root}/sub/b", total=False) == {f"{root}/sub/b": 2}
+
+
+def test_archived_restore(s3):
+    fs = rfsspec.RustyS3FileSystem(endpoint_url=endpoint_uri)
+    fn = f"{test_bucket_name}/archived"
+    fs.pipe(fn, b"cold data", storage_class="GLACIER")
+    assert fs.info(fn)["StorageClass"] == "GLACIER"
+    assert fs.restore_status(fn) == "archived"
+    with pytest.raises(rfsspec.ObjectArchivedError):
+        fs.cat(fn)
+    with pytest.raises(rfsspec.ObjectArchivedError):
+        fs.get_file(fn, "/dev/null")
+
+    # standard objects are left alone
+    fs.pipe(f"{test_bucket_name}/warm", b"warm data")
+    assert fs.restore_status(f"{test_bucket_name}/warm") == "available"
+    fs.restore([fn, f"{test_bucket_name}/warm"], days=2, tier="Bulk")
+    assert fs.restore_status([fn, f"{test_bucket_name}/missing"]) in (
+        ["in-progress", None], ["restored", None])
+    if fs.restore_status(fn) == "restored":
+        assert fs.info(fn)["RestoreExpiryDate"]
+        assert fs.cat(fn) == b"cold data"
+
+
+def test_object_lock(s3):
+    import datetime
+
+    client = get_boto3_client()
+    client.create_bucket(Bucket="rusty-locked", ObjectLockEnabledForBucket=True)
+    fs = rfsspec.RustyS3FileSystem(endpoint_url=endpoint_uri)
+    until = datetime.datetime.now(datetime.timezone.utc) + datetime.timedelta(days=1)
+
+    fn = "rusty-locked/held"
+    fs.pipe(fn, b"audit", object_lock_mode="GOVERNANCE", object_lock_retain_until=until,
+            object_lock_legal_hold=True)
+    info = fs.info(fn)
+    assert info["ObjectLockMode"] == "GOVERNANCE"
+    assert info["ObjectLockLegalHoldStatus"] == "ON"
+    assert fs.get_retention(fn)["Mode"] == "GOVERNANCE"
+    assert fs.get_legal_hold(fn)
+    fs.put_legal_hold(fn, on=False)
+    assert not fs.get_legal_hold(fn)
+
+    fn2 = "rusty-locked/later"
+    with fs.open(fn2, "wb", block_size=5 * 2**20, object_lock_legal_hold=True) as f:
+        f.write(b"0" * (6 * 2**20))
+    assert fs.get_legal_hold(fn2)
+    assert fs.get_retention(fn2) == {}
+    fs.put_retention(fn2, "GOVERNANCE", until.timestamp())
+    assert fs.get_retention(fn2)["Mode"] == "GOVERNANCE"
+
+    with pytest.raises(ValueError):
+        fs.pipe("rusty-locked/bad", b"", object_lock_mode="GOVERNANCE")
+
+
+def test_locations(s3):
+    fs = rfsspec.RustyS3FileSystem(endpoint_url=endpoint_uri)
+    fs.pipe(f"{test_bucket_name}/deep/a file", b"data")
+
+    for url in [
+        f"s3://{test_bucket_name}/deep/a file",
+        f"https://{test_bucket_name}.s3.us-east-1.amazonaws.com/deep/a%20file",
+        f"https://s3.amazonaws.com/{test_bucket_name}/deep/a%20file?x-id=GetObject",
+    ]:
+        assert fs.cat_file(url) == b"data"
+        assert fs.info(url)["size"] == 4
+
+    for bad in [
+        test_bucket_name,  # no key
+        "/deep/a file",  # no bucket
+        "bad bucket!/key",
+        "https://example.com/bucket/key",
+        "arn:aws:s3::123456789012:accesspoint/mrap/key",  # multi-region
+        "arn:aws:s3:us-east-1:123456789012:bucket/key",
+    ]:
+        with pytest.raises(ValueError):
+            fs.cat_file(bad)
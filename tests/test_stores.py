@@ -0,0 +1,34 @@
+import pytest
+
+
+@pytest.fixture(params=["gcs", "azure"])
+def store(request):
+    """Each of the emulated stores, as (filesystem, bucket or container)"""
+    return request.getfixturevalue(request.param)
+
+
+def test_glob(store):
+    fs, bucket = store
+    root = f"{bucket}/globs"
+    for key in ["a.csv", "b.txt", "sub/c.csv", "sub/deep/d.csv", "sub2/e.csv"]:
+        fs.pipe(f"{root}/{key}", b"data")
+
+    assert fs.glob(f"{root}/*.csv") == [f"{root}/a.csv"]
+    assert fs.glob(f"{root}/*/*.csv") == [f"{root}/sub/c.csv", f"{root}/sub2/e.csv"]
+    assert fs.glob(f"{root}/**/*.csv") == [
+        f"{root}/a.csv", f"{root}/sub/c.csv", f"{root}/sub/deep/d.csv", f"{root}/sub2/e.csv"]
+    assert fs.glob(f"{root}/**/*.csv", maxdepth=2) == [
+        f"{root}/a.csv", f"{root}/sub/c.csv", f"{root}/sub2/e.csv"]
+    assert fs.glob(f"{root}/*.parquet") == []
+
+
+def test_du(store):
+    fs, bucket = store
+    root = f"{bucket}/du"
+    for key, data in {"a": b"1", "sub/b": b"22", "sub/deep/c": b"333"}.items():
+        fs.pipe(f"{root}/{key}", data)
+
+    assert fs.du(root) == 6
+    assert fs.du(root, total=False) == {root: 6, f"{root}/sub": 5, f"{root}/sub/deep": 3}
+    assert fs.du(f"{root}/sub/b") == 2
+    assert fs.du(f"{bucket}/nothing") == 0